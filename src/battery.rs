@@ -0,0 +1,91 @@
+use ev3dev_lang_rust::{Ev3Result, PowerSupply};
+use std::time::{Duration, Instant};
+
+/// Interval between two voltage samples.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+/// Time constant of the voltage filter in seconds, smooths out the sag of single accelerations.
+const VOLTAGE_FILTER: f32 = 1.0;
+/// Limits of the sag compensation, so a bad reading can not drive the motors wild.
+const MIN_COMPENSATION: f32 = 0.8;
+const MAX_COMPENSATION: f32 = 1.5;
+
+/// Charge state of the battery.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BatteryState {
+    Ok,
+    /// The battery is low, the speed is capped.
+    Warning,
+    /// The battery is about to brown out the brick, the motors are stopped.
+    Critical,
+}
+
+impl BatteryState {
+    pub fn id(self) -> u8 {
+        match self {
+            BatteryState::Ok => 0,
+            BatteryState::Warning => 1,
+            BatteryState::Critical => 2,
+        }
+    }
+}
+
+/// Filtered voltage of the battery.
+pub struct Battery {
+    power: PowerSupply,
+    voltage: Option<f32>,
+    last_sample: Instant,
+}
+
+impl Battery {
+    pub fn open() -> Ev3Result<Battery> {
+        Ok(Battery {
+            power: PowerSupply::new()?,
+            voltage: None,
+            last_sample: Instant::now(),
+        })
+    }
+
+    /// Sample the voltage if due. Returns the filtered voltage in volts.
+    pub fn update(&mut self) -> Ev3Result<f32> {
+        let elapsed = self.last_sample.elapsed();
+        if let Some(voltage) = self.voltage {
+            if elapsed < SAMPLE_INTERVAL {
+                return Ok(voltage);
+            }
+        }
+
+        let measured = self.power.get_voltage_now()? as f32 / 1_000_000.0;
+        let dt = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+        let voltage = match self.voltage {
+            Some(voltage) => voltage + dt / (VOLTAGE_FILTER + dt) * (measured - voltage),
+            None => measured,
+        };
+
+        self.voltage = Some(voltage);
+        self.last_sample = Instant::now();
+
+        Ok(voltage)
+    }
+
+    /// Current drawn from the battery in ampere.
+    pub fn current(&self) -> Ev3Result<f32> {
+        Ok(self.power.get_current_now()? as f32 / 1_000_000.0)
+    }
+
+    /// Factor for duty cycles to drive at the same speed as with the nominal voltage. A nominal
+    /// voltage of zero disables the compensation.
+    pub fn compensation(&mut self, nominal: f32) -> Ev3Result<f32> {
+        if nominal <= 0.0 {
+            return Ok(1.0);
+        }
+
+        let voltage = self.update()?;
+        if voltage <= 0.0 {
+            return Ok(1.0);
+        }
+
+        Ok((nominal / voltage)
+            .max(MIN_COMPENSATION)
+            .min(MAX_COMPENSATION))
+    }
+}
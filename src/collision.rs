@@ -0,0 +1,114 @@
+/// Encoder speed of a drive motor at full duty cycle in degrees per second.
+const FULL_SPEED: f32 = 900.0;
+/// Drop of the encoder speed within one cycle, in degrees per second, that signals a collision
+/// if all driven wheels see it at once.
+const COLLISION_DROP: f32 = 250.0;
+/// Largest change of the commanded speed within one cycle that is not a wanted braking.
+const COMMAND_CHANGE: f32 = 0.05;
+/// Smallest commanded speed of a wheel to take it into account.
+const MINIMUM_SPEED: f32 = 0.2;
+/// Difference between the measured to commanded speed ratios of two wheels that signals slip.
+const SLIP_RATIO: f32 = 0.5;
+/// Number of consecutive cycles with a ratio difference before a wheel counts as slipping.
+const SLIP_CYCLES: u32 = 10;
+
+/// Event detected from the wheel encoders.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DriveEvent {
+    /// All driven wheels decelerated suddenly without being told to.
+    Collision,
+    /// The wheel with the given index turns much faster than the others.
+    Slip(usize),
+}
+
+/// Compares commanded and measured wheel speeds to detect collisions and slipping wheels.
+pub struct CollisionDetector {
+    last_commanded: Vec<f32>,
+    last_measured: Vec<f32>,
+    slip_cycles: u32,
+}
+
+impl CollisionDetector {
+    pub fn new() -> CollisionDetector {
+        CollisionDetector {
+            last_commanded: Vec::new(),
+            last_measured: Vec::new(),
+            slip_cycles: 0,
+        }
+    }
+
+    /// Feed the commanded wheel speeds (between -1 and 1) and the measured encoder speeds of
+    /// the next cycle. Returns an event once when it starts.
+    pub fn update(&mut self, commanded: &[f32], measured: &[i32]) -> Option<DriveEvent> {
+        let measured: Vec<f32> = measured.iter().map(|&speed| speed as f32).collect();
+
+        let event = if self.last_measured.len() == measured.len() {
+            self.detect_collision(commanded, &measured)
+                .or_else(|| self.detect_slip(commanded, &measured))
+        } else {
+            None
+        };
+
+        self.last_commanded = commanded.to_vec();
+        self.last_measured = measured;
+
+        event
+    }
+
+    fn detect_collision(&self, commanded: &[f32], measured: &[f32]) -> Option<DriveEvent> {
+        let mut driven = 0;
+
+        for (index, &speed) in commanded.iter().enumerate() {
+            if speed.abs() < MINIMUM_SPEED {
+                continue;
+            }
+            driven += 1;
+
+            let steady = (speed - self.last_commanded[index]).abs() < COMMAND_CHANGE;
+            let drop = (self.last_measured[index] - measured[index]) * speed.signum();
+            if !steady || drop < COLLISION_DROP {
+                return None;
+            }
+        }
+
+        if driven > 0 {
+            Some(DriveEvent::Collision)
+        } else {
+            None
+        }
+    }
+
+    fn detect_slip(&mut self, commanded: &[f32], measured: &[f32]) -> Option<DriveEvent> {
+        // Ratio of the measured to the expected speed of every driven wheel
+        let ratios: Vec<(usize, f32)> = commanded
+            .iter()
+            .enumerate()
+            .filter(|&(_, speed)| speed.abs() >= MINIMUM_SPEED)
+            .map(|(index, speed)| (index, measured[index] / (speed * FULL_SPEED)))
+            .collect();
+
+        if ratios.len() < 2 {
+            self.slip_cycles = 0;
+            return None;
+        }
+
+        let minimum = ratios.iter().fold(f32::INFINITY, |a, &(_, r)| a.min(r));
+        let (wheel, maximum) =
+            ratios
+                .iter()
+                .cloned()
+                .fold((0, f32::NEG_INFINITY), |a, b| if b.1 > a.1 { b } else { a });
+
+        if maximum - minimum < SLIP_RATIO {
+            self.slip_cycles = 0;
+            return None;
+        }
+
+        self.slip_cycles += 1;
+        if self.slip_cycles == SLIP_CYCLES {
+            Some(DriveEvent::Slip(wheel))
+        } else {
+            None
+        }
+    }
+}
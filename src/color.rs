@@ -0,0 +1,83 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
+/// Raw rgb reading of the color sensor.
+pub type Rgb = (i32, i32, i32);
+
+/// Hue and saturation of a color in the hsv color space. The brightness (value) is dropped.
+/// Hue is given in degrees, saturation between 0 and 1.
+#[derive(Clone, Copy, Debug)]
+pub struct Hsv {
+    pub hue: f32,
+    pub saturation: f32,
+}
+
+impl Hsv {
+    pub fn from_rgb(rgb: &Rgb) -> Hsv {
+        let r = rgb.0.max(0) as f32;
+        let g = rgb.1.max(0) as f32;
+        let b = rgb.2.max(0) as f32;
+
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+
+        let hue = if delta <= 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * (((g - b) / delta) % 6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+
+        Hsv {
+            hue: if hue < 0.0 { hue + 360.0 } else { hue },
+            saturation: if max <= 0.0 { 0.0 } else { delta / max },
+        }
+    }
+
+    /// Distance between the hue and saturation of both colors, ignoring the brightness.
+    ///
+    /// Both colors are placed on the chroma disc (hue as angle, saturation as radius), so two
+    /// unsaturated colors are close to each other regardless of their hue. The result lies
+    /// between 0 and 2.
+    pub fn chroma_distance(&self, other: &Hsv) -> f32 {
+        let (x1, y1) = self.chroma();
+        let (x2, y2) = other.chroma();
+
+        ((x1 - x2).powi(2) + (y1 - y2).powi(2)).sqrt()
+    }
+
+    fn chroma(&self) -> (f32, f32) {
+        let angle = self.hue * PI / 180.0;
+        (self.saturation * angle.cos(), self.saturation * angle.sin())
+    }
+}
+
+pub fn get_saved_color<P: AsRef<Path>>(name: P, default: i32) -> Rgb {
+    let file = String::from(
+        fs::read_to_string(name)
+            .unwrap_or_else(|_| String::from("0;0;0"))
+            .trim(),
+    );
+    let vec: Vec<&str> = file.split(';').collect::<Vec<&str>>();
+
+    let r = vec[0].parse::<i32>().unwrap_or(default);
+    let g = vec[1].parse::<i32>().unwrap_or(default);
+    let b = vec[2].parse::<i32>().unwrap_or(default);
+
+    (r, g, b)
+}
+pub fn save_color<P: AsRef<Path>>(name: P, foreground: &Rgb) {
+    let mut color = String::new();
+    color.push_str(foreground.0.to_string().as_ref());
+    color.push(';');
+    color.push_str(foreground.1.to_string().as_ref());
+    color.push(';');
+    color.push_str(foreground.2.to_string().as_ref());
+
+    fs::write(name, color).unwrap();
+}
@@ -0,0 +1,334 @@
+use std::f32;
+
+/// Gains of a pid controller in physical units: the integral gain acts on error seconds, the
+/// derivative gain on error per second.
+#[derive(Clone, Copy, Debug)]
+pub struct Gains {
+    pub proportional: f32,
+    pub integral: f32,
+    pub derivative: f32,
+}
+
+impl Gains {
+    pub fn new(proportional: f32, integral: f32, derivative: f32) -> Gains {
+        Gains {
+            proportional,
+            integral,
+            derivative,
+        }
+    }
+}
+
+/// Table of gains for different operating points, for example speeds. Between two points the
+/// gains are interpolated linearly, outside of the table the nearest point is used.
+#[derive(Clone, Debug)]
+pub struct GainSchedule {
+    points: Vec<(f32, Gains)>,
+}
+
+impl GainSchedule {
+    pub fn new(mut points: Vec<(f32, Gains)>) -> GainSchedule {
+        points.retain(|point| point.0.is_finite());
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        GainSchedule { points }
+    }
+
+    pub fn points(&self) -> &[(f32, Gains)] {
+        &self.points
+    }
+
+    /// Gains at the given operating point. Returns `None` if the table is empty.
+    pub fn gains(&self, point: f32) -> Option<Gains> {
+        let first = self.points.first()?;
+        let last = self.points.last()?;
+
+        if point <= first.0 {
+            return Some(first.1);
+        }
+        if point >= last.0 {
+            return Some(last.1);
+        }
+
+        let window = self.points.windows(2).find(|window| point <= window[1].0)?;
+        let (lower, upper) = (window[0], window[1]);
+        let ratio = (point - lower.0) / (upper.0 - lower.0);
+        let lerp = |a: f32, b: f32| a + ratio * (b - a);
+
+        Some(Gains::new(
+            lerp(lower.1.proportional, upper.1.proportional),
+            lerp(lower.1.integral, upper.1.integral),
+            lerp(lower.1.derivative, upper.1.derivative),
+        ))
+    }
+}
+
+/// Strategy to keep the integral from winding up while the output is saturated.
+#[derive(Clone, Copy, Debug)]
+pub enum AntiWindup {
+    /// Stop integrating while the output is saturated, unless the error drives it back.
+    Clamping,
+    /// Feed the saturation back into the integral with the given tracking gain.
+    BackCalculation(f32),
+}
+
+/// Generic pid controller with anti windup, derivative filter and output limits.
+pub struct Controller {
+    gains: Gains,
+    anti_windup: AntiWindup,
+    output_minimum: f32,
+    output_maximum: f32,
+    derivative_filter: f32,
+    integral_limit: f32,
+    integral: f32,
+    derivative: f32,
+    last_error: Option<f32>,
+    output: f32,
+}
+
+impl Controller {
+    /// Create an unlimited controller with clamping anti windup and without derivative filter.
+    pub fn new(gains: Gains) -> Controller {
+        Controller {
+            gains,
+            anti_windup: AntiWindup::Clamping,
+            output_minimum: f32::NEG_INFINITY,
+            output_maximum: f32::INFINITY,
+            derivative_filter: 0.0,
+            integral_limit: f32::INFINITY,
+            integral: 0.0,
+            derivative: 0.0,
+            last_error: None,
+            output: 0.0,
+        }
+    }
+
+    pub fn with_output_limits(mut self, minimum: f32, maximum: f32) -> Controller {
+        self.output_minimum = minimum;
+        self.output_maximum = maximum;
+        self
+    }
+
+    pub fn with_anti_windup(mut self, anti_windup: AntiWindup) -> Controller {
+        self.anti_windup = anti_windup;
+        self
+    }
+
+    /// Low pass the derivative with the given time constant in seconds. Zero disables the filter.
+    pub fn with_derivative_filter(mut self, time_constant: f32) -> Controller {
+        self.derivative_filter = time_constant.max(0.0);
+        self
+    }
+
+    /// Keep the integral between the negative and the positive limit, in error seconds.
+    pub fn with_integral_limit(mut self, limit: f32) -> Controller {
+        self.integral_limit = limit.abs();
+        self
+    }
+
+    pub fn set_gains(&mut self, gains: Gains) {
+        self.gains = gains;
+    }
+
+    /// Accumulated error in error seconds.
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    pub fn set_integral(&mut self, integral: f32) {
+        self.integral = integral.max(-self.integral_limit).min(self.integral_limit);
+    }
+
+    /// Filtered derivative of the error in error per second.
+    pub fn derivative(&self) -> f32 {
+        self.derivative
+    }
+
+    /// Clear the integral, the derivative and the error history.
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.derivative = 0.0;
+        self.last_error = None;
+        self.output = 0.0;
+    }
+
+    /// Calculate the next output for the given error and time step in seconds.
+    pub fn update(&mut self, error: f32, dt: f32) -> f32 {
+        if dt <= 0.0 {
+            return self.output;
+        }
+
+        let raw_derivative = match self.last_error {
+            Some(last_error) => (error - last_error) / dt,
+            None => 0.0,
+        };
+        self.last_error = Some(error);
+
+        let alpha = dt / (self.derivative_filter + dt);
+        self.derivative += alpha * (raw_derivative - self.derivative);
+
+        let integral = (self.integral + error * dt)
+            .max(-self.integral_limit)
+            .min(self.integral_limit);
+        let unsaturated = self.gains.proportional * error
+            + self.gains.integral * integral
+            + self.gains.derivative * self.derivative;
+        let output = unsaturated
+            .max(self.output_minimum)
+            .min(self.output_maximum);
+
+        let integral = match self.anti_windup {
+            AntiWindup::Clamping => {
+                let saturated = unsaturated != output;
+                let winding_up = unsaturated.signum() == error.signum();
+
+                if saturated && winding_up {
+                    self.integral
+                } else {
+                    integral
+                }
+            }
+            AntiWindup::BackCalculation(tracking) => {
+                integral + tracking * (output - unsaturated) * dt
+            }
+        };
+        self.integral = integral.max(-self.integral_limit).min(self.integral_limit);
+
+        self.output = output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn proportional_output() {
+        let mut controller = Controller::new(Gains::new(2.0, 0.0, 0.0));
+        assert!(close(controller.update(0.5, DT), 1.0));
+        assert!(close(controller.update(-0.25, DT), -0.5));
+    }
+
+    #[test]
+    fn integral_accumulates_error_seconds() {
+        let mut controller = Controller::new(Gains::new(0.0, 2.0, 0.0));
+        for _ in 0..50 {
+            controller.update(0.1, DT);
+        }
+        assert!(close(controller.integral(), 0.1));
+        assert!(close(controller.update(0.0, DT), 0.2));
+    }
+
+    #[test]
+    fn derivative_of_error_per_second() {
+        let mut controller = Controller::new(Gains::new(0.0, 0.0, 1.0));
+        // No derivative without an error history
+        assert!(close(controller.update(0.2, DT), 0.0));
+        assert!(close(controller.update(0.4, DT), 10.0));
+        assert!(close(controller.derivative(), 10.0));
+    }
+
+    #[test]
+    fn derivative_filter_smooths_steps() {
+        let mut controller = Controller::new(Gains::new(0.0, 0.0, 1.0)).with_derivative_filter(DT);
+        controller.update(0.0, DT);
+        // Half of the step with a time constant equal to the time step
+        assert!(close(controller.update(0.2, DT), 5.0));
+        assert!(close(controller.update(0.2, DT), 2.5));
+    }
+
+    #[test]
+    fn output_limits() {
+        let mut controller =
+            Controller::new(Gains::new(10.0, 0.0, 0.0)).with_output_limits(-1.0, 0.5);
+        assert!(close(controller.update(1.0, DT), 0.5));
+        assert!(close(controller.update(-1.0, DT), -1.0));
+        assert!(close(controller.update(0.01, DT), 0.1));
+    }
+
+    #[test]
+    fn clamping_stops_integrating_while_saturated() {
+        let mut controller = Controller::new(Gains::new(1.0, 1.0, 0.0))
+            .with_output_limits(-1.0, 1.0)
+            .with_anti_windup(AntiWindup::Clamping);
+        for _ in 0..100 {
+            assert!(close(controller.update(2.0, DT), 1.0));
+        }
+        assert!(close(controller.integral(), 0.0));
+
+        // Errors driving the output back are integrated
+        controller.update(-0.5, DT);
+        assert!(close(controller.integral(), -0.01));
+    }
+
+    #[test]
+    fn back_calculation_unwinds_the_integral() {
+        let mut controller = Controller::new(Gains::new(1.0, 1.0, 0.0))
+            .with_output_limits(-1.0, 1.0)
+            .with_anti_windup(AntiWindup::BackCalculation(1.0));
+        for _ in 0..500 {
+            assert!(close(controller.update(2.0, DT), 1.0));
+        }
+
+        // The saturation is fed back, so the integral settles where the error and the
+        // saturation cancel instead of growing without bound
+        assert!((controller.integral() - 0.96).abs() < 0.01);
+
+        // And the output leaves the limit as soon as the error changes sign
+        assert!(controller.update(-0.5, DT) < 1.0);
+    }
+
+    #[test]
+    fn integral_limit() {
+        let mut controller = Controller::new(Gains::new(0.0, 1.0, 0.0)).with_integral_limit(0.1);
+        for _ in 0..100 {
+            controller.update(1.0, DT);
+        }
+        assert!(close(controller.integral(), 0.1));
+
+        controller.set_integral(-5.0);
+        assert!(close(controller.integral(), -0.1));
+    }
+
+    #[test]
+    fn reset_clears_the_state() {
+        let mut controller = Controller::new(Gains::new(1.0, 1.0, 1.0));
+        controller.update(0.5, DT);
+        controller.update(1.0, DT);
+        controller.reset();
+
+        assert!(close(controller.integral(), 0.0));
+        assert!(close(controller.derivative(), 0.0));
+        // Without an error history the first derivative is zero again
+        assert!(close(controller.update(0.5, DT), 0.5 + 0.5 * DT));
+    }
+
+    #[test]
+    fn non_positive_time_step_keeps_the_output() {
+        let mut controller = Controller::new(Gains::new(1.0, 1.0, 1.0));
+        let output = controller.update(0.5, DT);
+        let integral = controller.integral();
+
+        assert!(close(controller.update(2.0, 0.0), output));
+        assert!(close(controller.update(2.0, -1.0), output));
+        assert!(close(controller.integral(), integral));
+    }
+
+    #[test]
+    fn gain_schedule_interpolates() {
+        let schedule = GainSchedule::new(vec![
+            (1.0, Gains::new(2.0, 0.0, 0.0)),
+            (0.0, Gains::new(1.0, 0.0, 0.0)),
+        ]);
+        assert!(close(schedule.gains(0.5).unwrap().proportional, 1.5));
+        assert!(close(schedule.gains(-1.0).unwrap().proportional, 1.0));
+        assert!(close(schedule.gains(2.0).unwrap().proportional, 2.0));
+        assert!(GainSchedule::new(vec![]).gains(0.5).is_none());
+    }
+}
@@ -0,0 +1,229 @@
+use battery::Battery;
+use driving;
+use driving::DrivingCommand;
+use monitor::{CRITICAL_VOLTAGE, WARNING_VOLTAGE};
+use network::NetworkCommand;
+use pid;
+use pid::PidCommand;
+use profile;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+/// Time to wait for the checks of the drive thread, which may still calibrate the kicker.
+const DRIVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time to wait for the checks of the pid thread.
+const SENSOR_TIMEOUT: Duration = Duration::from_secs(3);
+/// Whether the self-test runs at startup. It pulses the motors, so it is off by default.
+const STARTUP_FILE: &str = "startup_self_test";
+
+/// Outcome of a single check, ordered by severity.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Outcome {
+    Pass,
+    /// The robot works, but something should be looked at.
+    Warning,
+    Fail,
+}
+
+impl Outcome {
+    pub fn id(self) -> u8 {
+        match self {
+            Outcome::Pass => 0,
+            Outcome::Warning => 1,
+            Outcome::Fail => 2,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Pass => "PASS",
+            Outcome::Warning => "WARN",
+            Outcome::Fail => "FAIL",
+        }
+    }
+}
+
+/// Result of checking one part of the robot.
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub name: String,
+    pub outcome: Outcome,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(name: &str, outcome: Outcome, detail: String) -> Check {
+        Check {
+            name: name.to_string(),
+            outcome,
+            detail,
+        }
+    }
+
+    pub fn pass(name: &str, detail: String) -> Check {
+        Check::new(name, Outcome::Pass, detail)
+    }
+
+    pub fn warning(name: &str, detail: String) -> Check {
+        Check::new(name, Outcome::Warning, detail)
+    }
+
+    pub fn fail(name: &str, detail: String) -> Check {
+        Check::new(name, Outcome::Fail, detail)
+    }
+}
+
+/// Results of a self-test.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Worst outcome of all checks.
+    pub fn outcome(&self) -> Outcome {
+        self.checks.iter().fold(Outcome::Pass, |worst, check| {
+            if check.outcome > worst {
+                check.outcome
+            } else {
+                worst
+            }
+        })
+    }
+
+    fn print(&self) {
+        println!("Self-test: {}", self.outcome().label());
+        for check in &self.checks {
+            println!(
+                "  {} {}: {}",
+                check.outcome.label(),
+                check.name,
+                check.detail
+            );
+        }
+    }
+}
+
+fn check_battery() -> Check {
+    let voltage = match Battery::open().and_then(|mut battery| battery.update()) {
+        Ok(voltage) => voltage,
+        Err(e) => return Check::fail("battery", format!("not readable: {:?}", e)),
+    };
+
+    let detail = format!("{:.2} V", voltage);
+    if voltage < CRITICAL_VOLTAGE {
+        Check::fail("battery", detail)
+    } else if voltage < WARNING_VOLTAGE {
+        Check::warning("battery", detail)
+    } else {
+        Check::pass("battery", detail)
+    }
+}
+
+/// Whether a settings file, if present, only holds numbers. Unreadable settings silently fall
+/// back to their defaults.
+fn is_valid_settings<P: AsRef<Path>>(path: P) -> bool {
+    match fs::read_to_string(path) {
+        Ok(file) => file
+            .trim()
+            .split(&[';', ':'][..])
+            .filter(|value| !value.is_empty())
+            .all(|value| value.parse::<f32>().is_ok()),
+        Err(_) => true,
+    }
+}
+
+/// Check the settings files, each a list of numbers separated by semicolons or colons.
+fn check_config() -> Check {
+    let mut invalid: Vec<String> = driving::SETTINGS_FILES
+        .iter()
+        .chain(pid::SETTINGS_FILES.iter())
+        .chain([STARTUP_FILE].iter())
+        .filter(|file| !is_valid_settings(file))
+        .map(|file| file.to_string())
+        .collect();
+
+    invalid.extend(
+        profile::profile_files()
+            .iter()
+            .filter(|file| !is_valid_settings(profile::path(file)))
+            .map(|file| profile::path(file).display().to_string()),
+    );
+
+    if Path::new("profile").exists() && profile::active().is_none() {
+        invalid.push("profile".to_string());
+    }
+
+    if !invalid.is_empty() {
+        return Check::warning(
+            "config",
+            format!("defaults used for {}", invalid.join(", ")),
+        );
+    }
+
+    let profile = profile::active().unwrap_or_else(|| String::from("shared settings"));
+    Check::pass("config", format!("using {}", profile))
+}
+
+fn run(driving: &Sender<DrivingCommand>, pid: &Sender<PidCommand>) -> Report {
+    // The motors are pulsed, so the line follower must not drive
+    pid.send(PidCommand::Stop).unwrap();
+
+    let mut checks = Vec::new();
+
+    let (sender, receiver) = mpsc::channel();
+    driving
+        .send(DrivingCommand::Diagnose(Sender::clone(&sender)))
+        .unwrap();
+    match receiver.recv_timeout(DRIVE_TIMEOUT) {
+        Ok(drive_checks) => checks.extend(drive_checks),
+        Err(_) => checks.push(Check::fail(
+            "motors",
+            "no response, is a motor missing?".to_string(),
+        )),
+    }
+
+    pid.send(PidCommand::Diagnose(sender)).unwrap();
+    match receiver.recv_timeout(SENSOR_TIMEOUT) {
+        Ok(sensor_checks) => checks.extend(sensor_checks),
+        Err(_) => checks.push(Check::fail(
+            "color sensors",
+            "no response, is a sensor missing?".to_string(),
+        )),
+    }
+
+    checks.push(check_battery());
+    checks.push(check_config());
+
+    Report { checks }
+}
+
+/// Run a self-test of motors, kicker, color sensors, battery and settings in the background.
+/// The report is printed and sent to the server.
+pub fn start(
+    driving: Sender<DrivingCommand>,
+    pid: Sender<PidCommand>,
+    network: Sender<NetworkCommand>,
+) {
+    thread::Builder::new()
+        .name("Diagnostics".to_string())
+        .spawn(move || {
+            let report = run(&driving, &pid);
+            report.print();
+            network.send(NetworkCommand::Diagnostics(report)).unwrap();
+        })
+        .unwrap();
+}
+
+pub fn get_saved_startup() -> bool {
+    fs::read_to_string(STARTUP_FILE)
+        .map(|file| file.trim() == "1")
+        .unwrap_or(false)
+}
+pub fn save_startup(enabled: bool) {
+    fs::write(STARTUP_FILE, if enabled { "1" } else { "0" }).unwrap();
+}
@@ -0,0 +1,210 @@
+use std::f32::consts::PI;
+
+use ev3dev_lang_rust::motors::{LargeMotor, MediumMotor, MotorPort};
+use ev3dev_lang_rust::Ev3Result;
+
+/// Motor degrees of the steering at full lock.
+const STEERING_RANGE: f32 = 45.0;
+/// Smallest forward speed used to calculate the steering angle, so the robot steers standing.
+const STEERING_MINIMUM_SPEED: f32 = 0.1;
+
+/// Motion of the robot, all components between -1 and 1.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Motion {
+    pub forward: f32,
+    /// Sideways speed, positive to the left. Only omni drives can move sideways.
+    pub lateral: f32,
+    /// Rotation, positive counterclockwise.
+    pub rotation: f32,
+}
+
+impl Motion {
+    pub fn new(forward: f32, lateral: f32, rotation: f32) -> Motion {
+        Motion {
+            forward,
+            lateral,
+            rotation,
+        }
+    }
+
+    /// Motion of a differential drive with the given left and right track speeds.
+    pub fn from_tracks(left: f32, right: f32) -> Motion {
+        Motion::new((left + right) / 2.0, 0.0, (right - left) / 2.0)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.forward == 0.0 && self.lateral == 0.0 && self.rotation == 0.0
+    }
+}
+
+/// Arrangement of the drive motors. The wheels alternate between the left and the right slew
+/// limits in the order of their motors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DrivetrainKind {
+    /// Left track on port B and right track on port A.
+    Tank,
+    /// Three omni wheels: front left on port B, back on port D and front right on port A.
+    Omni3,
+    /// Four omni wheels in x arrangement: front left on port B, back left on port C, back right
+    /// on port D and front right on port A. There is no port left for the kicker.
+    Omni4,
+    /// Car steering: drive motor on port B and a medium steering motor on port D.
+    Ackermann,
+}
+
+impl DrivetrainKind {
+    pub fn from_id(id: u8) -> DrivetrainKind {
+        match id {
+            1 => DrivetrainKind::Omni3,
+            2 => DrivetrainKind::Omni4,
+            3 => DrivetrainKind::Ackermann,
+            _ => DrivetrainKind::Tank,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            DrivetrainKind::Tank => 0,
+            DrivetrainKind::Omni3 => 1,
+            DrivetrainKind::Omni4 => 2,
+            DrivetrainKind::Ackermann => 3,
+        }
+    }
+
+    fn ports(self) -> Vec<MotorPort> {
+        match self {
+            DrivetrainKind::Tank => vec![MotorPort::OutB, MotorPort::OutA],
+            DrivetrainKind::Omni3 => vec![MotorPort::OutB, MotorPort::OutD, MotorPort::OutA],
+            DrivetrainKind::Omni4 => vec![
+                MotorPort::OutB,
+                MotorPort::OutC,
+                MotorPort::OutD,
+                MotorPort::OutA,
+            ],
+            DrivetrainKind::Ackermann => vec![MotorPort::OutB],
+        }
+    }
+
+    /// Whether port C is free for the kicker.
+    pub fn has_kicker(self) -> bool {
+        self != DrivetrainKind::Omni4
+    }
+
+    /// Speeds of the drive motors for a motion, scaled down evenly to full speed.
+    pub fn wheel_speeds(self, motion: Motion) -> Vec<f32> {
+        let speeds = match self {
+            DrivetrainKind::Tank => vec![
+                motion.forward - motion.rotation,
+                motion.forward + motion.rotation,
+            ],
+            DrivetrainKind::Omni3 => omni(motion, &[60.0, 180.0, 300.0]),
+            DrivetrainKind::Omni4 => omni(motion, &[45.0, 135.0, 225.0, 315.0]),
+            DrivetrainKind::Ackermann => vec![motion.forward],
+        };
+
+        let maximum = speeds
+            .iter()
+            .fold(1.0, |maximum: f32, speed| maximum.max(speed.abs()));
+        speeds.iter().map(|speed| speed / maximum).collect()
+    }
+
+    /// Steering between -1 (right) and 1 (left) for a motion, the curvature follows the ratio of
+    /// rotation and forward speed.
+    fn steering(self, motion: Motion) -> f32 {
+        let forward = motion.forward.abs().max(STEERING_MINIMUM_SPEED) * motion.forward.signum();
+        (motion.rotation / forward).max(-1.0).min(1.0)
+    }
+}
+
+/// Speeds of omni wheels at the given angles (degrees counterclockwise from the front), each
+/// rolling counterclockwise around the center.
+fn omni(motion: Motion, angles: &[f32]) -> Vec<f32> {
+    angles
+        .iter()
+        .map(|angle| {
+            let angle = angle * PI / 180.0;
+            -angle.sin() * motion.forward + angle.cos() * motion.lateral + motion.rotation
+        })
+        .collect()
+}
+
+/// The drive motors of the configured drivetrain.
+pub struct Drivetrain {
+    kind: DrivetrainKind,
+    motors: Vec<LargeMotor>,
+    steering: Option<MediumMotor>,
+    steering_position: i32,
+}
+
+impl Drivetrain {
+    /// Open the motors and stop them.
+    pub fn open(kind: DrivetrainKind) -> Ev3Result<Drivetrain> {
+        let mut motors = Vec::new();
+        for port in kind.ports() {
+            let motor = LargeMotor::get(port)?;
+            motor.set_duty_cycle_sp(0)?;
+            motor.run_direct()?;
+            motors.push(motor);
+        }
+
+        let steering = if kind == DrivetrainKind::Ackermann {
+            let steering = MediumMotor::get(MotorPort::OutD)?;
+            steering.set_stop_action(MediumMotor::STOP_ACTION_HOLD)?;
+            steering.set_position(0)?;
+            steering.set_speed_sp(500)?;
+            Some(steering)
+        } else {
+            None
+        };
+
+        Ok(Drivetrain {
+            kind,
+            motors,
+            steering,
+            steering_position: 0,
+        })
+    }
+
+    pub fn kind(&self) -> DrivetrainKind {
+        self.kind
+    }
+
+    pub fn set_duty_cycle(&self, motor: usize, duty_cycle: i32) -> Ev3Result<()> {
+        self.motors[motor].set_duty_cycle_sp(duty_cycle)
+    }
+
+    /// Encoder speed of a drive motor in degrees per second.
+    pub fn speed(&self, motor: usize) -> Ev3Result<i32> {
+        self.motors[motor].get_speed()
+    }
+
+    /// Encoder position of a drive motor in degrees.
+    pub fn position(&self, motor: usize) -> Ev3Result<i32> {
+        self.motors[motor].get_position()
+    }
+
+    /// Name of the port of a drive motor.
+    pub fn port_name(&self, motor: usize) -> String {
+        format!("{:?}", self.kind.ports()[motor])
+    }
+
+    /// Whether a drive motor reports a stall or an overload.
+    pub fn is_blocked(&self, motor: usize) -> Ev3Result<bool> {
+        Ok(self.motors[motor].get_state()?.iter().any(|state| {
+            state == LargeMotor::STATE_STALLED || state == LargeMotor::STATE_OVERLOADED
+        }))
+    }
+
+    /// Turn the steering motor for the motion, if there is one.
+    pub fn steer(&mut self, motion: Motion) -> Ev3Result<()> {
+        if let Some(ref steering) = self.steering {
+            let position = (self.kind.steering(motion) * STEERING_RANGE) as i32;
+            if position != self.steering_position {
+                self.steering_position = position;
+                steering.run_to_abs_pos(Some(position))?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,30 +1,144 @@
+use std::fs;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
 
-use ev3dev_lang_rust::motors::{LargeMotor, MediumMotor, MotorPort};
+use battery::Battery;
+use collision::{CollisionDetector, DriveEvent};
+use diagnostics::Check;
+use drivetrain::{Drivetrain, DrivetrainKind, Motion};
+use ev3dev_lang_rust::motors::{MediumMotor, MotorPort};
 use ev3dev_lang_rust::Ev3Result;
+use kinematics::{arcade, WheelGeometry};
+use network::NetworkCommand;
+use shaping::InputShaping;
+use slew::{SlewLimiter, SlewLimits};
+use stall::StallDetector;
 
 const MAX_SPEED: u8 = 100;
 const PID_SPEED: f32 = 0.5;
-const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+/// Interval of the mixer that applies the slew limits and the watchdog.
+const MIXER_INTERVAL: Duration = Duration::from_millis(20);
+const DEFAULT_ACCELERATION: f32 = 5.0;
+const DEFAULT_JERK: f32 = 0.0;
+const DEFAULT_DEADBAND: f32 = 0.05;
+const DEFAULT_EXPO: f32 = 0.0;
+const DEFAULT_TRACK_WIDTH: f32 = 0.12;
+const DEFAULT_MAX_WHEEL_SPEED: f32 = 0.5;
+/// Battery voltage the drive speeds are calibrated for, in volts.
+const DEFAULT_NOMINAL_VOLTAGE: f32 = 7.5;
+const KICK_DURATION: Duration = Duration::from_millis(200);
+/// Kicker position at the end of a kick.
+const KICK_POSITION: i32 = 150;
+/// Largest distance of the kicker from its target position that passes the self-test.
+const KICK_TOLERANCE: i32 = 30;
+/// Duty cycle and duration of the self-test pulse of a drive motor.
+const PULSE_DUTY: i32 = 40;
+const PULSE_DURATION: Duration = Duration::from_millis(300);
+/// Smallest encoder travel of a self-test pulse in degrees.
+const PULSE_TRAVEL: i32 = 20;
+/// Duration and speed of backing off from an obstacle after a collision.
+const BACK_OFF_DURATION: Duration = Duration::from_millis(400);
+const BACK_OFF_SPEED: f32 = 0.4;
+/// Time without manual commands or heartbeats after which the manual speeds are dropped.
+const MANUAL_DEADLINE: Duration = Duration::from_millis(500);
+/// Time without pid speeds after which they are dropped.
+const PID_DEADLINE: Duration = Duration::from_millis(300);
+
+const CONTROL_MODE_FILE: &str = "control_mode";
+const DRIVETRAIN_FILE: &str = "drivetrain";
+const SLEW_LIMITS_FILE: &str = "slew_limits";
+const NOMINAL_VOLTAGE_FILE: &str = "nominal_voltage";
+const BACK_OFF_FILE: &str = "back_off";
+const INPUT_SHAPING_FILE: &str = "input_shaping";
+const WHEEL_GEOMETRY_FILE: &str = "wheel_geometry";
+/// Settings files of the drive.
+pub const SETTINGS_FILES: [&str; 7] = [
+    CONTROL_MODE_FILE,
+    DRIVETRAIN_FILE,
+    SLEW_LIMITS_FILE,
+    NOMINAL_VOLTAGE_FILE,
+    BACK_OFF_FILE,
+    INPUT_SHAPING_FILE,
+    WHEEL_GEOMETRY_FILE,
+];
 
 use std::time::Duration;
-use std::time::SystemTime;
+use std::time::Instant;
+
+/// Arbitration between the manual and the pid speeds.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ControlMode {
+    /// Only the manual speeds drive the robot.
+    Manual,
+    /// Only the line follower drives the robot.
+    Autonomous,
+    /// Manual and pid speeds are blended, the weight of the pid speeds is between 0 and 1.
+    Shared(f32),
+}
 
-fn perform_drive(driving_receiver: &Receiver<DrivingCommand>) -> Ev3Result<()> {
-    let mut pid_left_speed: f32 = 0.0;
-    let mut pid_right_speed: f32 = 0.0;
+impl ControlMode {
+    pub fn from_id(id: u8, weight: f32) -> ControlMode {
+        match id {
+            1 => ControlMode::Autonomous,
+            2 => ControlMode::Shared(weight.max(0.0).min(1.0)),
+            _ => ControlMode::Manual,
+        }
+    }
 
-    let mut left_speed: f32 = 0.0;
-    let mut right_speed: f32 = 0.0;
+    pub fn id(self) -> (u8, f32) {
+        match self {
+            ControlMode::Manual => (0, 0.0),
+            ControlMode::Autonomous => (1, 1.0),
+            ControlMode::Shared(weight) => (2, weight),
+        }
+    }
 
-    let speed: f32 = MAX_SPEED as f32;
+    /// Motion of the robot from the manual and the pid motion.
+    fn mix(self, manual: Motion, pid: Motion) -> Motion {
+        match self {
+            ControlMode::Manual => manual,
+            ControlMode::Autonomous => pid,
+            ControlMode::Shared(weight) => Motion::new(
+                (1.0 - weight) * manual.forward + weight * pid.forward,
+                (1.0 - weight) * manual.lateral + weight * pid.lateral,
+                (1.0 - weight) * manual.rotation + weight * pid.rotation,
+            ),
+        }
+    }
+}
+
+/// Time of the last sign of life of an input source.
+struct Heartbeat {
+    deadline: Duration,
+    last: Instant,
+}
 
-    let mut kick: Option<SystemTime> = None;
+impl Heartbeat {
+    fn new(deadline: Duration) -> Heartbeat {
+        Heartbeat {
+            deadline,
+            last: Instant::now(),
+        }
+    }
 
-    let right_motor = LargeMotor::get(MotorPort::OutA)?;
-    let left_motor = LargeMotor::get(MotorPort::OutB)?;
+    fn beat(&mut self) {
+        self.last = Instant::now();
+    }
+
+    fn expired(&self) -> bool {
+        self.last.elapsed() > self.deadline
+    }
+}
+
+/// One slew limiter per drive motor, alternating between the left and the right limits.
+fn slew_limiters(kind: DrivetrainKind, left: SlewLimits, right: SlewLimits) -> Vec<SlewLimiter> {
+    (0..kind.wheel_speeds(Motion::default()).len())
+        .map(|index| SlewLimiter::new(if index % 2 == 0 { left } else { right }))
+        .collect()
+}
+
+fn open_kicker() -> Ev3Result<MediumMotor> {
     let kicker = MediumMotor::get(MotorPort::OutC)?;
 
     //Calibrate kicker
@@ -37,11 +151,127 @@ fn perform_drive(driving_receiver: &Receiver<DrivingCommand>) -> Ev3Result<()> {
     kicker.set_position(0)?;
     kicker.set_speed_sp(850)?;
 
-    //Stop running motors
-    left_motor.set_duty_cycle_sp(0)?;
-    right_motor.set_duty_cycle_sp(0)?;
-    left_motor.run_direct()?;
-    right_motor.run_direct()?;
+    Ok(kicker)
+}
+
+/// Pulse a drive motor forward and check that its encoder follows in the same direction.
+fn check_motor(drivetrain: &Drivetrain, motor: usize) -> Ev3Result<Check> {
+    let name = format!("motor {}", drivetrain.port_name(motor));
+
+    let start = drivetrain.position(motor)?;
+    drivetrain.set_duty_cycle(motor, PULSE_DUTY)?;
+    thread::sleep(PULSE_DURATION);
+    drivetrain.set_duty_cycle(motor, 0)?;
+    thread::sleep(PULSE_DURATION);
+    let travel = drivetrain.position(motor)? - start;
+
+    let detail = format!("{} degrees", travel);
+    Ok(if travel >= PULSE_TRAVEL {
+        Check::pass(&name, detail)
+    } else if travel <= -PULSE_TRAVEL {
+        Check::fail(&name, format!("{}, turns backwards", detail))
+    } else {
+        Check::fail(&name, format!("{}, no encoder response", detail))
+    })
+}
+
+/// Kick once and check that the kicker reaches both end positions.
+fn check_kicker(kicker: &MediumMotor) -> Ev3Result<Check> {
+    kicker.run_to_abs_pos(Some(KICK_POSITION))?;
+    thread::sleep(KICK_DURATION * 2);
+    let kicked = kicker.get_position()?;
+    kicker.run_to_abs_pos(Some(0))?;
+    thread::sleep(KICK_DURATION * 2);
+    let returned = kicker.get_position()?;
+
+    let detail = format!("travel {} to {} degrees", returned, kicked);
+    Ok(
+        if (kicked - KICK_POSITION).abs() <= KICK_TOLERANCE && returned.abs() <= KICK_TOLERANCE {
+            Check::pass("kicker", detail)
+        } else {
+            Check::fail("kicker", detail)
+        },
+    )
+}
+
+/// Self-test of the drive motors and the kicker. The robot must stand still.
+fn check_drive(
+    drivetrain: &Drivetrain,
+    kicker: &Option<MediumMotor>,
+    motors: usize,
+    stall_count: u32,
+) -> Vec<Check> {
+    let mut checks: Vec<Check> = (0..motors)
+        .map(|motor| {
+            check_motor(drivetrain, motor).unwrap_or_else(|e| {
+                let _ = drivetrain.set_duty_cycle(motor, 0);
+                Check::fail(
+                    &format!("motor {}", drivetrain.port_name(motor)),
+                    format!("{:?}", e),
+                )
+            })
+        })
+        .collect();
+
+    checks.push(match *kicker {
+        Some(ref kicker) => {
+            check_kicker(kicker).unwrap_or_else(|e| Check::fail("kicker", format!("{:?}", e)))
+        }
+        None => Check::pass("kicker", "not fitted".to_string()),
+    });
+
+    let detail = format!("{} since start", stall_count);
+    checks.push(if stall_count > 0 {
+        Check::warning("stalls", detail)
+    } else {
+        Check::pass("stalls", detail)
+    });
+
+    checks
+}
+
+fn perform_drive(
+    driving_receiver: &Receiver<DrivingCommand>,
+    network: &Sender<NetworkCommand>,
+) -> Ev3Result<()> {
+    let mut mode = get_saved_control_mode();
+    // Whether the manual mode is overridden while the line follower runs
+    let mut pid_override = false;
+
+    let mut pid = Motion::default();
+    let mut manual = Motion::default();
+
+    let speed: f32 = MAX_SPEED as f32;
+
+    let mut drivetrain = Drivetrain::open(get_saved_drivetrain())?;
+
+    let (mut left_limits, mut right_limits) = get_saved_slew_limits();
+    let mut limiters = slew_limiters(drivetrain.kind(), left_limits, right_limits);
+    let mut duty_cycles = vec![0; limiters.len()];
+    let mut stalls: Vec<StallDetector> = limiters.iter().map(|_| StallDetector::new()).collect();
+    let mut stall_count: u32 = 0;
+    let mut collision = CollisionDetector::new();
+
+    let mut back_off_enabled = get_saved_back_off();
+    let mut back_off: Option<(Instant, Motion)> = None;
+
+    let mut battery = Battery::open()?;
+    let mut nominal_voltage = get_saved_nominal_voltage();
+    let mut speed_cap: f32 = 1.0;
+
+    let mut shaping = get_saved_input_shaping();
+    let mut geometry = get_saved_wheel_geometry();
+
+    let mut kick: Option<Instant> = None;
+
+    let mut manual_heartbeat = Heartbeat::new(MANUAL_DEADLINE);
+    let mut pid_heartbeat = Heartbeat::new(PID_DEADLINE);
+
+    let mut kicker = if drivetrain.kind().has_kicker() {
+        Some(open_kicker()?)
+    } else {
+        None
+    };
 
     /*
             devices.extra_motor.speed_sp = 850
@@ -52,60 +282,278 @@ fn perform_drive(driving_receiver: &Receiver<DrivingCommand>) -> Ev3Result<()> {
             devices.extra_motor.run_to_abs_pos()
     */
 
+    let mut last_tick = Instant::now();
+    let mut next_tick = last_tick + MIXER_INTERVAL;
+
     loop {
-        if let Ok(driving) = driving_receiver.recv_timeout(RECEIVE_TIMEOUT) {
-            let mut drive_change = false;
-
-            match driving {
-                DrivingCommand::SetTrack(left, right) => {
-                    left_speed = left;
-                    right_speed = right;
-                    drive_change = true;
-                }
-                DrivingCommand::SetPid(left, right) => {
-                    pid_left_speed = left * PID_SPEED;
-                    pid_right_speed = right * PID_SPEED;
-                    drive_change = true;
-                }
-                DrivingCommand::SetTrim(_) => {}
-                DrivingCommand::Kick => {
-                    if kick.is_none() {
-                        kick = Some(SystemTime::now());
-                        kicker.run_to_abs_pos(Some(150))?;
+        let now = Instant::now();
+        if now < next_tick {
+            if let Ok(driving) = driving_receiver.recv_timeout(next_tick - now) {
+                match driving {
+                    DrivingCommand::SetTrack(left, right) => {
+                        manual = Motion::from_tracks(left, right);
+                        manual_heartbeat.beat();
+                    }
+                    DrivingCommand::SetArcade(throttle, steer) => {
+                        let (left, right) = arcade(shaping.apply(throttle), shaping.apply(steer));
+                        manual = Motion::from_tracks(left, right);
+                        manual_heartbeat.beat();
+                    }
+                    DrivingCommand::SetVelocity(velocity, yaw_rate) => {
+                        let (left, right) = geometry.velocity(velocity, yaw_rate);
+                        manual = Motion::from_tracks(left, right);
+                        manual_heartbeat.beat();
+                    }
+                    DrivingCommand::SetMotion(motion) => {
+                        manual = motion;
+                        manual_heartbeat.beat();
+                    }
+                    DrivingCommand::SetDrivetrain(kind) => {
+                        for (motor, limiter) in limiters.iter_mut().enumerate() {
+                            limiter.reset(0.0);
+                            duty_cycles[motor] = 0;
+                            drivetrain.set_duty_cycle(motor, 0)?;
+                        }
+
+                        // Only keep a drivetrain whose motors are all connected, the saved one
+                        // is opened again after every drive error
+                        match Drivetrain::open(kind) {
+                            Ok(opened) => {
+                                save_drivetrain(kind);
+                                drivetrain = opened;
+                                limiters = slew_limiters(kind, left_limits, right_limits);
+                                duty_cycles = vec![0; limiters.len()];
+                                stalls = limiters.iter().map(|_| StallDetector::new()).collect();
+                                collision = CollisionDetector::new();
+
+                                kicker = None;
+                                if kind.has_kicker() {
+                                    match open_kicker() {
+                                        Ok(opened) => kicker = Some(opened),
+                                        Err(e) => println!("No kicker found! {:?}", e),
+                                    }
+                                }
+                            }
+                            Err(e) => {
+                                println!("Drivetrain {:?} not available! {:?}", kind, e);
+                            }
+                        }
+                        network
+                            .send(NetworkCommand::Drivetrain(drivetrain.kind()))
+                            .unwrap();
+                    }
+                    DrivingCommand::SetBackOff(enabled) => {
+                        back_off_enabled = enabled;
+                        save_back_off(enabled);
+                    }
+                    DrivingCommand::SetSpeedCap(cap) => {
+                        speed_cap = cap.max(0.0).min(1.0);
+                    }
+                    DrivingCommand::SetNominalVoltage(voltage) => {
+                        nominal_voltage = voltage.max(0.0);
+                        save_nominal_voltage(nominal_voltage);
+                    }
+                    DrivingCommand::SetInputShaping(input_shaping) => {
+                        shaping = input_shaping;
+                        save_input_shaping(shaping);
+                    }
+                    DrivingCommand::SetWheelGeometry(wheel_geometry) => {
+                        geometry = wheel_geometry;
+                        save_wheel_geometry(geometry);
+                    }
+                    DrivingCommand::SetPid(left, right) => {
+                        pid = Motion::from_tracks(left * PID_SPEED, right * PID_SPEED);
+                        pid_heartbeat.beat();
+                    }
+                    DrivingCommand::Heartbeat => {
+                        manual_heartbeat.beat();
+                    }
+                    DrivingCommand::SetPidActive(active) => {
+                        // Servers that only start the line follower expect it to drive,
+                        // independent of the saved control mode
+                        if active && mode == ControlMode::Manual {
+                            pid_override = true;
+                            mode = ControlMode::Autonomous;
+                            network.send(NetworkCommand::ControlMode(mode)).unwrap();
+                        } else if !active && pid_override {
+                            pid_override = false;
+                            mode = get_saved_control_mode();
+                            network.send(NetworkCommand::ControlMode(mode)).unwrap();
+                        }
+                    }
+                    DrivingCommand::SetControlMode(control_mode) => {
+                        pid_override = false;
+                        mode = control_mode;
+                        save_control_mode(mode);
+                        network.send(NetworkCommand::ControlMode(mode)).unwrap();
+                    }
+                    DrivingCommand::SetSlewLimits(left, right) => {
+                        left_limits = left;
+                        right_limits = right;
+                        for (index, limiter) in limiters.iter_mut().enumerate() {
+                            limiter.set_limits(if index % 2 == 0 { left } else { right });
+                        }
+                        save_slew_limits(left, right);
+                    }
+                    DrivingCommand::EmergencyStop => {
+                        // Bypass the slew limits and stop at once
+                        manual = Motion::default();
+                        pid = Motion::default();
+                        back_off = None;
+                        for (motor, limiter) in limiters.iter_mut().enumerate() {
+                            limiter.reset(0.0);
+                            duty_cycles[motor] = 0;
+                            drivetrain.set_duty_cycle(motor, 0)?;
+                        }
+                    }
+                    DrivingCommand::Diagnose(reply) => {
+                        manual = Motion::default();
+                        pid = Motion::default();
+                        back_off = None;
+                        kick = None;
+                        for (motor, limiter) in limiters.iter_mut().enumerate() {
+                            limiter.reset(0.0);
+                            duty_cycles[motor] = 0;
+                            drivetrain.set_duty_cycle(motor, 0)?;
+                        }
+
+                        let checks =
+                            check_drive(&drivetrain, &kicker, duty_cycles.len(), stall_count);
+                        let _ = reply.send(checks);
+
+                        collision = CollisionDetector::new();
+                        last_tick = Instant::now();
+                        next_tick = last_tick + MIXER_INTERVAL;
+                    }
+                    DrivingCommand::SetTrim(_) => {}
+                    DrivingCommand::Kick => {
+                        if let Some(ref kicker) = kicker {
+                            if kick.is_none() {
+                                kick = Some(Instant::now());
+                                kicker.run_to_abs_pos(Some(KICK_POSITION))?;
+                            }
+                        }
+                    }
+                    DrivingCommand::Stop => {
+                        return Ok(());
                     }
                 }
-                DrivingCommand::Stop => {
-                    return Ok(());
+            }
+            continue;
+        }
+
+        let dt = now.duration_since(last_tick);
+        let dt = dt.as_secs() as f32 + dt.subsec_nanos() as f32 * 1e-9;
+        last_tick = now;
+        next_tick += MIXER_INTERVAL;
+        if next_tick < now {
+            next_tick = now + MIXER_INTERVAL;
+        }
+
+        // Stop the speeds of every source that stopped sending, independent of the thread
+        // that feeds it
+        if manual_heartbeat.expired() && !manual.is_zero() {
+            println!("manual drive timed out");
+            manual = Motion::default();
+        }
+        if pid_heartbeat.expired() && !pid.is_zero() {
+            println!("pid drive timed out");
+            pid = Motion::default();
+        }
+
+        if let Some((time, _)) = back_off {
+            if time.elapsed() > BACK_OFF_DURATION {
+                back_off = None;
+            }
+        }
+
+        let motion = match back_off {
+            Some((_, motion)) => motion,
+            None => mode.mix(manual, pid),
+        };
+        drivetrain.steer(motion)?;
+
+        // Scale the duty cycles by the battery sag, so the same speeds drive equally fast over
+        // a whole match. A failed voltage reading drives uncompensated for this tick
+        let compensation = battery.compensation(nominal_voltage).unwrap_or(1.0);
+
+        let wheel_speeds = drivetrain.kind().wheel_speeds(motion);
+        let mut commanded = Vec::with_capacity(limiters.len());
+        let mut measured = Vec::with_capacity(limiters.len());
+        for (motor, limiter) in limiters.iter_mut().enumerate() {
+            let wheel_speed = limiter.update(wheel_speeds[motor] * speed_cap, dt);
+            let duty_cycle = (wheel_speed * compensation * speed).max(-speed).min(speed) as i32;
+            commanded.push(wheel_speed);
+
+            // Reduce the power of a blocked motor before it overheats. A failed reading skips
+            // the detection for this tick
+            let stall = &mut stalls[motor];
+            if let (Ok(motor_speed), Ok(blocked)) =
+                (drivetrain.speed(motor), drivetrain.is_blocked(motor))
+            {
+                measured.push(motor_speed);
+                if stall.update(duty_cycle, motor_speed, blocked) {
+                    stall_count += 1;
+                    println!("motor {} stalled", motor);
+                    network
+                        .send(NetworkCommand::Stall(motor as u8, stall_count))
+                        .unwrap();
                 }
             }
+            let duty_cycle = stall.limit(duty_cycle);
 
-            if drive_change {
-                let left = (pid_left_speed + left_speed).max(-1.0).min(1.0);
-                let right = (pid_right_speed + right_speed).max(-1.0).min(1.0);
+            if duty_cycle != duty_cycles[motor] {
+                duty_cycles[motor] = duty_cycle;
+                drivetrain.set_duty_cycle(motor, duty_cycle)?;
+            }
+        }
 
-                left_motor.set_duty_cycle_sp((left * speed) as i32)?;
-                right_motor.set_duty_cycle_sp((right * speed) as i32)?;
+        let event = if measured.len() == commanded.len() {
+            collision.update(&commanded, &measured)
+        } else {
+            None
+        };
+        match event {
+            Some(DriveEvent::Collision) if back_off.is_none() => {
+                println!("collision detected");
+                network.send(NetworkCommand::Collision).unwrap();
+
+                if back_off_enabled {
+                    // Drive away from the obstacle in the opposite direction
+                    let away = Motion::new(-motion.forward, -motion.lateral, 0.0);
+                    let length = (away.forward * away.forward + away.lateral * away.lateral).sqrt();
+                    if length > 0.0 {
+                        let scale = BACK_OFF_SPEED / length;
+                        let away = Motion::new(away.forward * scale, away.lateral * scale, 0.0);
+                        back_off = Some((Instant::now(), away));
+                    }
+                }
             }
+            Some(DriveEvent::Slip(motor)) => {
+                println!("motor {} slipping", motor);
+                network.send(NetworkCommand::Slip(motor as u8)).unwrap();
+            }
+            _ => {}
         }
 
         if let Some(time) = kick {
-            let duration = time.elapsed().unwrap();
-            let elapsed = (duration.as_secs() as u32 * 1000) + duration.subsec_millis();
-            if elapsed > 200 {
+            if time.elapsed() > KICK_DURATION {
                 kick = None;
-                kicker.run_to_abs_pos(Some(0))?;
+                if let Some(ref kicker) = kicker {
+                    kicker.run_to_abs_pos(Some(0))?;
+                }
             }
         }
     }
 }
 
-pub fn start() -> Sender<DrivingCommand> {
+pub fn start(network: Sender<NetworkCommand>) -> Sender<DrivingCommand> {
     let (driving_sender, driving_receiver) = mpsc::channel();
 
     thread::Builder::new()
         .name("Driving".to_string())
         .spawn(move || loop {
-            match perform_drive(&driving_receiver) {
+            match perform_drive(&driving_receiver, &network) {
                 Ok(_) => {
                     break;
                 }
@@ -119,11 +567,140 @@ pub fn start() -> Sender<DrivingCommand> {
     driving_sender
 }
 
+pub fn get_saved_control_mode() -> ControlMode {
+    let file = fs::read_to_string(CONTROL_MODE_FILE).unwrap_or_else(|_| String::from("0;0"));
+    let vec: Vec<&str> = file.trim().split(';').collect::<Vec<&str>>();
+
+    let id = vec[0].parse::<u8>().unwrap_or(0);
+    let weight = vec
+        .get(1)
+        .and_then(|w| w.parse::<f32>().ok())
+        .unwrap_or(0.0);
+
+    ControlMode::from_id(id, weight)
+}
+fn save_control_mode(mode: ControlMode) {
+    let (id, weight) = mode.id();
+    fs::write(CONTROL_MODE_FILE, format!("{};{}", id, weight)).unwrap();
+}
+
+fn get_saved_drivetrain() -> DrivetrainKind {
+    let id = fs::read_to_string(DRIVETRAIN_FILE)
+        .unwrap_or_else(|_| String::new())
+        .trim()
+        .parse::<u8>()
+        .unwrap_or(0);
+    DrivetrainKind::from_id(id)
+}
+fn save_drivetrain(kind: DrivetrainKind) {
+    fs::write(DRIVETRAIN_FILE, kind.id().to_string()).unwrap();
+}
+
+fn get_saved_slew_limits() -> (SlewLimits, SlewLimits) {
+    let file = fs::read_to_string(SLEW_LIMITS_FILE).unwrap_or_else(|_| String::new());
+    let vec: Vec<f32> = file
+        .trim()
+        .split(';')
+        .filter_map(|limit| limit.parse::<f32>().ok())
+        .collect();
+
+    if vec.len() == 4 {
+        (
+            SlewLimits::new(vec[0], vec[1]),
+            SlewLimits::new(vec[2], vec[3]),
+        )
+    } else {
+        let limits = SlewLimits::new(DEFAULT_ACCELERATION, DEFAULT_JERK);
+        (limits, limits)
+    }
+}
+fn save_slew_limits(left: SlewLimits, right: SlewLimits) {
+    let file = format!(
+        "{};{};{};{}",
+        left.acceleration, left.jerk, right.acceleration, right.jerk
+    );
+    fs::write(SLEW_LIMITS_FILE, file).unwrap();
+}
+
+/// Read a file of two numbers separated by a semicolon.
+fn get_saved_pair(name: &str) -> Option<(f32, f32)> {
+    let file = fs::read_to_string(name).ok()?;
+    let vec: Vec<&str> = file.trim().split(';').collect::<Vec<&str>>();
+
+    Some((
+        vec[0].parse::<f32>().ok()?,
+        vec.get(1)?.parse::<f32>().ok()?,
+    ))
+}
+fn save_pair(name: &str, pair: (f32, f32)) {
+    fs::write(name, format!("{};{}", pair.0, pair.1)).unwrap();
+}
+
+fn get_saved_nominal_voltage() -> f32 {
+    fs::read_to_string(NOMINAL_VOLTAGE_FILE)
+        .unwrap_or_else(|_| String::new())
+        .trim()
+        .parse::<f32>()
+        .unwrap_or(DEFAULT_NOMINAL_VOLTAGE)
+}
+fn save_nominal_voltage(voltage: f32) {
+    fs::write(NOMINAL_VOLTAGE_FILE, voltage.to_string()).unwrap();
+}
+
+fn get_saved_back_off() -> bool {
+    fs::read_to_string(BACK_OFF_FILE)
+        .map(|file| file.trim() == "1")
+        .unwrap_or(false)
+}
+fn save_back_off(enabled: bool) {
+    fs::write(BACK_OFF_FILE, if enabled { "1" } else { "0" }).unwrap();
+}
+
+fn get_saved_input_shaping() -> InputShaping {
+    let (deadband, expo) =
+        get_saved_pair(INPUT_SHAPING_FILE).unwrap_or((DEFAULT_DEADBAND, DEFAULT_EXPO));
+    InputShaping::new(deadband, expo)
+}
+fn save_input_shaping(shaping: InputShaping) {
+    save_pair(INPUT_SHAPING_FILE, (shaping.deadband, shaping.expo))
+}
+fn get_saved_wheel_geometry() -> WheelGeometry {
+    let (track_width, max_wheel_speed) = get_saved_pair(WHEEL_GEOMETRY_FILE)
+        .unwrap_or((DEFAULT_TRACK_WIDTH, DEFAULT_MAX_WHEEL_SPEED));
+    WheelGeometry::new(track_width, max_wheel_speed)
+}
+fn save_wheel_geometry(geometry: WheelGeometry) {
+    save_pair(
+        WHEEL_GEOMETRY_FILE,
+        (geometry.track_width, geometry.max_wheel_speed),
+    )
+}
+
 #[allow(dead_code)]
 pub enum DrivingCommand {
     SetTrack(f32, f32),
+    SetArcade(f32, f32),
+    SetVelocity(f32, f32),
+    SetInputShaping(InputShaping),
+    SetWheelGeometry(WheelGeometry),
+    SetMotion(Motion),
+    SetNominalVoltage(f32),
+    SetSpeedCap(f32),
+    /// Whether to back off from obstacles after a collision.
+    SetBackOff(bool),
+    SetDrivetrain(DrivetrainKind),
     SetPid(f32, f32),
     SetTrim(f32),
+    /// Keeps the manual speeds alive, the pid speeds are kept alive by `SetPid` itself.
+    Heartbeat,
+    SetControlMode(ControlMode),
+    /// The line follower or the auto tuning starts or stops. Starting switches the manual mode
+    /// to autonomous until they stop.
+    SetPidActive(bool),
+    SetSlewLimits(SlewLimits, SlewLimits),
+    EmergencyStop,
+    /// Stop and run the self-test of the motors, the checks are sent back.
+    Diagnose(Sender<Vec<Check>>),
     Kick,
     Stop,
 }
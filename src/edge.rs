@@ -0,0 +1,93 @@
+/// Steering difference while crossing the line, relative to the speed.
+const SWITCH_TURN: f32 = 0.5;
+/// Maximal time in seconds of an edge switch before the new edge is used anyway.
+const SWITCH_TIME: f32 = 2.0;
+
+/// Edge of the line followed by a single sensor.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Edge {
+    Left,
+    Right,
+}
+
+impl Edge {
+    pub fn from_id(id: u8) -> Edge {
+        match id {
+            0 => Edge::Left,
+            _ => Edge::Right,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            Edge::Left => 0,
+            Edge::Right => 1,
+        }
+    }
+
+    /// Sign of the line error. On the right edge the line lies left of the sensor.
+    pub fn multiplier(self) -> f32 {
+        match self {
+            Edge::Left => 1.0,
+            Edge::Right => -1.0,
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum SwitchPhase {
+    Approach,
+    Cross,
+}
+
+/// Manoeuvre to cross the line on purpose and continue on its other edge.
+pub struct EdgeSwitch {
+    target: Edge,
+    side: f32,
+    phase: SwitchPhase,
+    time: f32,
+}
+
+impl EdgeSwitch {
+    /// Start switching from the currently followed edge to the other one.
+    pub fn new(current: Edge) -> EdgeSwitch {
+        EdgeSwitch {
+            target: match current {
+                Edge::Left => Edge::Right,
+                Edge::Right => Edge::Left,
+            },
+            side: current.multiplier(),
+            phase: SwitchPhase::Approach,
+            time: 0.0,
+        }
+    }
+
+    pub fn target(&self) -> Edge {
+        self.target
+    }
+
+    /// Whether the sensor did not cross the line in time, so it still follows the old edge.
+    pub fn timed_out(&self) -> bool {
+        self.time > SWITCH_TIME
+    }
+
+    /// Calculate the next wheel speeds from the sensor position (negative values lie on the
+    /// line) and the time step in seconds. Returns `None` when the sensor reached the other
+    /// edge or the switch timed out.
+    pub fn update(&mut self, position: f32, speed: f32, dt: f32) -> Option<(f32, f32)> {
+        self.time += dt;
+        if self.timed_out() {
+            return None;
+        }
+
+        if self.phase == SwitchPhase::Approach && position < -0.5 {
+            self.phase = SwitchPhase::Cross;
+        }
+        if self.phase == SwitchPhase::Cross && position > 0.5 {
+            return None;
+        }
+
+        let turn = speed * SWITCH_TURN * self.side;
+        Some((speed + turn, speed - turn))
+    }
+}
@@ -0,0 +1,145 @@
+use color::Rgb;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+/// Type and parameter of a filter stage.
+#[derive(Clone, Copy, Debug)]
+pub enum FilterKind {
+    /// Mean of the last n values.
+    MovingAverage(usize),
+    /// Median of the last n values, removes single outliers.
+    Median(usize),
+    /// Exponential smoothing with the given weight of the newest value.
+    Exponential(f32),
+    /// One dimensional kalman filter with the given ratio of process to measurement noise.
+    Kalman(f32),
+}
+
+impl FilterKind {
+    pub fn from_id(id: u8, parameter: f32) -> Option<FilterKind> {
+        if !parameter.is_finite() {
+            return None;
+        }
+
+        match id {
+            0 => Some(FilterKind::MovingAverage(parameter.max(1.0) as usize)),
+            1 => Some(FilterKind::Median(parameter.max(1.0) as usize)),
+            2 => Some(FilterKind::Exponential(parameter.max(0.0).min(1.0))),
+            3 => Some(FilterKind::Kalman(parameter.max(0.0))),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> (u8, f32) {
+        match self {
+            FilterKind::MovingAverage(size) => (0, size as f32),
+            FilterKind::Median(size) => (1, size as f32),
+            FilterKind::Exponential(weight) => (2, weight),
+            FilterKind::Kalman(ratio) => (3, ratio),
+        }
+    }
+}
+
+/// A single filter stage with its state.
+enum Filter {
+    MovingAverage(usize, VecDeque<f32>),
+    Median(usize, VecDeque<f32>),
+    Exponential(f32, Option<f32>),
+    Kalman {
+        ratio: f32,
+        estimate: Option<f32>,
+        covariance: f32,
+    },
+}
+
+impl Filter {
+    fn new(kind: FilterKind) -> Filter {
+        match kind {
+            FilterKind::MovingAverage(size) => Filter::MovingAverage(size, VecDeque::new()),
+            FilterKind::Median(size) => Filter::Median(size, VecDeque::new()),
+            FilterKind::Exponential(weight) => Filter::Exponential(weight, None),
+            FilterKind::Kalman(ratio) => Filter::Kalman {
+                ratio,
+                estimate: None,
+                covariance: 1.0,
+            },
+        }
+    }
+
+    fn update(&mut self, value: f32) -> f32 {
+        match *self {
+            Filter::MovingAverage(size, ref mut values) => {
+                push(values, size, value);
+                values.iter().sum::<f32>() / values.len() as f32
+            }
+            Filter::Median(size, ref mut values) => {
+                push(values, size, value);
+                let mut sorted: Vec<f32> = values.iter().cloned().collect();
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+                sorted[sorted.len() / 2]
+            }
+            Filter::Exponential(weight, ref mut last) => {
+                let next = match *last {
+                    Some(last) => last + weight * (value - last),
+                    None => value,
+                };
+                *last = Some(next);
+                next
+            }
+            Filter::Kalman {
+                ratio,
+                ref mut estimate,
+                ref mut covariance,
+            } => {
+                // Random walk model with a measurement noise of one
+                let next = match *estimate {
+                    Some(last) => {
+                        let prediction = *covariance + ratio;
+                        let gain = prediction / (prediction + 1.0);
+                        *covariance = (1.0 - gain) * prediction;
+                        last + gain * (value - last)
+                    }
+                    None => value,
+                };
+                *estimate = Some(next);
+                next
+            }
+        }
+    }
+}
+
+fn push(values: &mut VecDeque<f32>, size: usize, value: f32) {
+    values.push_back(value);
+    while values.len() > size {
+        values.pop_front();
+    }
+}
+
+/// Chain of filter stages applied to each channel of a color reading.
+pub struct ColorFilter {
+    channels: Vec<Vec<Filter>>,
+}
+
+impl ColorFilter {
+    pub fn new(kinds: &[FilterKind]) -> ColorFilter {
+        ColorFilter {
+            channels: (0..3)
+                .map(|_| kinds.iter().map(|&kind| Filter::new(kind)).collect())
+                .collect(),
+        }
+    }
+
+    pub fn update(&mut self, sensor: &Rgb) -> Rgb {
+        let values = [sensor.0, sensor.1, sensor.2];
+        let mut filtered = [0; 3];
+
+        for (index, stages) in self.channels.iter_mut().enumerate() {
+            let value = stages
+                .iter_mut()
+                .fold(values[index] as f32, |value, stage| stage.update(value));
+            filtered[index] = value.round() as i32;
+        }
+
+        (filtered[0], filtered[1], filtered[2])
+    }
+}
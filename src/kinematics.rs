@@ -0,0 +1,39 @@
+/// Dimensions of a differential drive.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WheelGeometry {
+    /// Distance between the left and the right wheel in meters.
+    pub track_width: f32,
+    /// Speed of a wheel at full duty cycle in meters per second.
+    pub max_wheel_speed: f32,
+}
+
+impl WheelGeometry {
+    pub fn new(track_width: f32, max_wheel_speed: f32) -> WheelGeometry {
+        WheelGeometry {
+            track_width: track_width.max(0.01),
+            max_wheel_speed: max_wheel_speed.max(0.01),
+        }
+    }
+
+    /// Wheel speeds for a body velocity in meters per second and a yaw rate in radians per
+    /// second, counterclockwise positive.
+    pub fn velocity(&self, velocity: f32, yaw_rate: f32) -> (f32, f32) {
+        let turn = yaw_rate * self.track_width / 2.0;
+        normalize(
+            (velocity - turn) / self.max_wheel_speed,
+            (velocity + turn) / self.max_wheel_speed,
+        )
+    }
+}
+
+/// Wheel speeds for a throttle and a steering value, positive steering turns right.
+pub fn arcade(throttle: f32, steer: f32) -> (f32, f32) {
+    normalize(throttle + steer, throttle - steer)
+}
+
+/// Scale both wheel speeds down evenly if one of them exceeds full speed, so the curve radius
+/// is kept.
+fn normalize(left: f32, right: f32) -> (f32, f32) {
+    let maximum = left.abs().max(right.abs()).max(1.0);
+    (left / maximum, right / maximum)
+}
@@ -0,0 +1,113 @@
+use std::time::{Duration, Instant};
+
+/// Travelled distance covered by one bin of the profile, in speed seconds.
+const BIN_DISTANCE: f32 = 0.05;
+/// Distance to look ahead for upcoming curves.
+const LOOKAHEAD: f32 = 0.3;
+/// Controller output above which the track counts as a curve.
+const CURVE_THRESHOLD: f32 = 0.5;
+/// Controller output below which the track counts as straight.
+const STRAIGHT_THRESHOLD: f32 = 0.15;
+
+/// Kind of the upcoming track section.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Section {
+    Curve,
+    Straight,
+}
+
+/// Curvature profile of a closed track, learned during the first lap.
+///
+/// The distance is estimated from the driven speed and the time, so positions of later laps at
+/// other speeds still map onto the learned profile.
+pub struct LapProfile {
+    profile: Vec<f32>,
+    learning: Option<Vec<f32>>,
+    distance: f32,
+    lap: u32,
+    started: Option<Instant>,
+}
+
+impl LapProfile {
+    pub fn new() -> LapProfile {
+        LapProfile {
+            profile: Vec::new(),
+            learning: None,
+            distance: 0.0,
+            lap: 0,
+            started: None,
+        }
+    }
+
+    /// Forget the learned profile, the next lap is learned again.
+    pub fn clear(&mut self) {
+        self.profile.clear();
+        self.reset();
+    }
+
+    /// Restart lap counting. The next start line begins the first lap.
+    pub fn reset(&mut self) {
+        self.learning = None;
+        self.distance = 0.0;
+        self.lap = 0;
+        self.started = None;
+    }
+
+    /// Record the controller output while driving with the given speed for `dt` seconds.
+    pub fn update(&mut self, output: f32, speed: f32, dt: f32) {
+        if self.started.is_none() {
+            return;
+        }
+
+        self.distance += speed.abs() * dt;
+
+        if let Some(ref mut learning) = self.learning {
+            let bin = (self.distance / BIN_DISTANCE) as usize;
+            while learning.len() <= bin {
+                learning.push(0.0);
+            }
+            learning[bin] = learning[bin].max(output.abs());
+        }
+    }
+
+    /// Pass the start line. Returns the number and time of the finished lap.
+    pub fn pass_start(&mut self) -> Option<(u32, Duration)> {
+        let finished = self.started.map(|started| (self.lap, started.elapsed()));
+
+        if let Some(learning) = self.learning.take() {
+            if !learning.is_empty() {
+                self.profile = learning;
+            }
+        } else if self.profile.is_empty() {
+            self.learning = Some(Vec::new());
+        }
+
+        self.lap += 1;
+        self.distance = 0.0;
+        self.started = Some(Instant::now());
+
+        finished
+    }
+
+    /// Classify the upcoming section of the track from the learned profile.
+    pub fn section(&self) -> Option<Section> {
+        if self.profile.is_empty() || self.learning.is_some() || self.started.is_none() {
+            return None;
+        }
+
+        let start = (self.distance / BIN_DISTANCE) as usize;
+        let end = ((self.distance + LOOKAHEAD) / BIN_DISTANCE) as usize;
+
+        let ahead = (start..end + 1)
+            .map(|bin| self.profile[bin % self.profile.len()])
+            .fold(0.0, f32::max);
+
+        if ahead > CURVE_THRESHOLD {
+            Some(Section::Curve)
+        } else if ahead < STRAIGHT_THRESHOLD {
+            Some(Section::Straight)
+        } else {
+            None
+        }
+    }
+}
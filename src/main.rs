@@ -4,20 +4,62 @@ extern crate ev3dev_lang_rust;
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
 
-use driving::DrivingCommand;
-use pid::PidCommand;
+use color::Rgb;
+use controller::Gains;
+use drivetrain::{DrivetrainKind, Motion};
+use driving::{ControlMode, DrivingCommand};
+use edge::Edge;
+use filter::FilterKind;
+use kinematics::WheelGeometry;
+use marker::Marker;
+use pid::{LineDetection, PidCommand};
+use recovery::RecoveryStrategy;
+use route::Branch;
+use sensor::{SensorLayout, SensorMode};
+use shaping::InputShaping;
+use slew::SlewLimits;
+use tune::TuningRule;
 
+mod battery;
+mod collision;
+mod color;
+mod controller;
+mod diagnostics;
+mod drivetrain;
 mod driving;
+mod edge;
+mod filter;
+mod kinematics;
+mod lap;
+mod marker;
+mod monitor;
 mod network;
 mod pid;
+mod profile;
+mod recovery;
+mod route;
+mod schedule;
+mod sensor;
+mod shaping;
+mod slew;
+mod stall;
 mod status;
+mod tune;
 
 fn main() {
     let (sender, receiver) = mpsc::channel();
 
     let network = network::start(Sender::clone(&sender));
-    let driving = driving::start();
+    let driving = driving::start(Sender::clone(&network));
     let pid = pid::start(Sender::clone(&driving), Sender::clone(&network));
+    monitor::start(Sender::clone(&driving), Sender::clone(&network));
+    if diagnostics::get_saved_startup() {
+        diagnostics::start(
+            Sender::clone(&driving),
+            Sender::clone(&pid),
+            Sender::clone(&network),
+        );
+    }
 
     //pid.send(PidCommand::Start).unwrap();
 
@@ -29,6 +71,9 @@ fn main() {
             RobotCommand::SetTrim(trim) => {
                 driving.send(DrivingCommand::SetTrim(trim)).unwrap();
             }
+            RobotCommand::Heartbeat => {
+                driving.send(DrivingCommand::Heartbeat).unwrap();
+            }
             RobotCommand::Kick => {
                 driving.send(DrivingCommand::Kick).unwrap();
             }
@@ -45,6 +90,123 @@ fn main() {
             RobotCommand::SetBackground => {
                 pid.send(PidCommand::SetBackground).unwrap();
             }
+            RobotCommand::SetLineDetection(detection) => {
+                pid.send(PidCommand::SetLineDetection(detection)).unwrap();
+            }
+            RobotCommand::SetMarker(marker) => {
+                pid.send(PidCommand::SetMarker(marker)).unwrap();
+            }
+            RobotCommand::SetRoute(branches) => {
+                pid.send(PidCommand::SetRoute(branches)).unwrap();
+            }
+            RobotCommand::SetRecovery(recovery) => {
+                pid.send(PidCommand::SetRecovery(recovery)).unwrap();
+            }
+            RobotCommand::SetRate(rate) => {
+                pid.send(PidCommand::SetRate(rate)).unwrap();
+            }
+            RobotCommand::SetSensorLayout(layout) => {
+                pid.send(PidCommand::SetSensorLayout(layout)).unwrap();
+            }
+            RobotCommand::SetSensorMode(mode, ambient_compensation) => {
+                pid.send(PidCommand::SetSensorMode(mode, ambient_compensation))
+                    .unwrap();
+            }
+            RobotCommand::SetFilters(filters) => {
+                pid.send(PidCommand::SetFilters(filters)).unwrap();
+            }
+            RobotCommand::SetEdge(edge) => {
+                pid.send(PidCommand::SetEdge(edge)).unwrap();
+            }
+            RobotCommand::ResetLaps => {
+                pid.send(PidCommand::ResetLaps).unwrap();
+            }
+            RobotCommand::SetGains(gains) => {
+                pid.send(PidCommand::SetGains(gains)).unwrap();
+            }
+            RobotCommand::AutoTune(rule) => {
+                pid.send(PidCommand::AutoTune(rule)).unwrap();
+            }
+            RobotCommand::SetGainSchedule(points) => {
+                pid.send(PidCommand::SetGainSchedule(points)).unwrap();
+            }
+            RobotCommand::CreateProfile(name) => {
+                pid.send(PidCommand::CreateProfile(name)).unwrap();
+            }
+            RobotCommand::SelectProfile(name) => {
+                pid.send(PidCommand::SelectProfile(name)).unwrap();
+            }
+            RobotCommand::DeleteProfile(name) => {
+                pid.send(PidCommand::DeleteProfile(name)).unwrap();
+            }
+            RobotCommand::ListProfiles => {
+                pid.send(PidCommand::ListProfiles).unwrap();
+            }
+            RobotCommand::SetForegroundColor(color) => {
+                pid.send(PidCommand::SetForegroundColor(color)).unwrap();
+            }
+            RobotCommand::SetBackgroundColor(color) => {
+                pid.send(PidCommand::SetBackgroundColor(color)).unwrap();
+            }
+            RobotCommand::GetCalibration => {
+                pid.send(PidCommand::GetCalibration).unwrap();
+            }
+            RobotCommand::SetControlMode(mode) => {
+                driving.send(DrivingCommand::SetControlMode(mode)).unwrap();
+            }
+            RobotCommand::EmergencyStop => {
+                driving.send(DrivingCommand::EmergencyStop).unwrap();
+                pid.send(PidCommand::Stop).unwrap();
+            }
+            RobotCommand::SetArcade(throttle, steer) => {
+                driving
+                    .send(DrivingCommand::SetArcade(throttle, steer))
+                    .unwrap();
+            }
+            RobotCommand::SetVelocity(velocity, yaw_rate) => {
+                driving
+                    .send(DrivingCommand::SetVelocity(velocity, yaw_rate))
+                    .unwrap();
+            }
+            RobotCommand::SetInputShaping(shaping) => {
+                driving
+                    .send(DrivingCommand::SetInputShaping(shaping))
+                    .unwrap();
+            }
+            RobotCommand::SetWheelGeometry(geometry) => {
+                driving
+                    .send(DrivingCommand::SetWheelGeometry(geometry))
+                    .unwrap();
+            }
+            RobotCommand::SetDrivetrain(kind) => {
+                driving.send(DrivingCommand::SetDrivetrain(kind)).unwrap();
+            }
+            RobotCommand::SetMotion(motion) => {
+                driving.send(DrivingCommand::SetMotion(motion)).unwrap();
+            }
+            RobotCommand::SetNominalVoltage(voltage) => {
+                driving
+                    .send(DrivingCommand::SetNominalVoltage(voltage))
+                    .unwrap();
+            }
+            RobotCommand::Diagnose => {
+                diagnostics::start(
+                    Sender::clone(&driving),
+                    Sender::clone(&pid),
+                    Sender::clone(&network),
+                );
+            }
+            RobotCommand::SetBackOff(enabled) => {
+                driving.send(DrivingCommand::SetBackOff(enabled)).unwrap();
+            }
+            RobotCommand::SetStartupDiagnose(enabled) => {
+                diagnostics::save_startup(enabled);
+            }
+            RobotCommand::SetSlewLimits(left, right) => {
+                driving
+                    .send(DrivingCommand::SetSlewLimits(left, right))
+                    .unwrap();
+            }
         };
     }
 }
@@ -56,6 +218,9 @@ pub enum RobotCommand {
     /// Message type: 12
     SetTrim(f32),
 
+    /// Any received message, keeps manual driving alive.
+    Heartbeat,
+
     /// Message type: 20
     Kick,
 
@@ -67,4 +232,103 @@ pub enum RobotCommand {
 
     /// Message type: 32
     SetBackground,
+
+    /// Message type: 33
+    SetLineDetection(LineDetection),
+
+    /// Message type: 34
+    SetMarker(Marker),
+
+    /// Message type: 35
+    SetRoute(Vec<Branch>),
+
+    /// Message type: 36
+    SetRecovery(RecoveryStrategy),
+
+    /// Message type: 37
+    SetRate(u32),
+
+    /// Message type: 38
+    SetSensorLayout(SensorLayout),
+
+    /// Message type: 39
+    SetSensorMode(SensorMode, bool),
+
+    /// Message type: 50
+    SetFilters(Vec<FilterKind>),
+
+    /// Message type: 51
+    SetEdge(Edge),
+
+    /// Message type: 52
+    ResetLaps,
+
+    /// Message type: 53
+    SetGains(Gains),
+
+    /// Message type: 54
+    AutoTune(TuningRule),
+
+    /// Message type: 55
+    SetGainSchedule(Vec<(f32, Gains)>),
+
+    /// Message type: 56
+    CreateProfile(String),
+
+    /// Message type: 57
+    SelectProfile(String),
+
+    /// Message type: 58
+    DeleteProfile(String),
+
+    /// Message type: 59
+    ListProfiles,
+
+    /// Message type: 60
+    SetForegroundColor(Rgb),
+
+    /// Message type: 61
+    SetBackgroundColor(Rgb),
+
+    /// Message type: 62
+    GetCalibration,
+
+    /// Message type: 63
+    SetControlMode(ControlMode),
+
+    /// Message type: 64
+    EmergencyStop,
+
+    /// Message type: 65
+    SetSlewLimits(SlewLimits, SlewLimits),
+
+    /// Message type: 66
+    SetArcade(f32, f32),
+
+    /// Message type: 67
+    SetVelocity(f32, f32),
+
+    /// Message type: 68
+    SetInputShaping(InputShaping),
+
+    /// Message type: 69
+    SetWheelGeometry(WheelGeometry),
+
+    /// Message type: 70
+    SetDrivetrain(DrivetrainKind),
+
+    /// Message type: 71
+    SetMotion(Motion),
+
+    /// Message type: 72
+    SetNominalVoltage(f32),
+
+    /// Message type: 73
+    SetBackOff(bool),
+
+    /// Message type: 74
+    Diagnose,
+
+    /// Message type: 75
+    SetStartupDiagnose(bool),
 }
@@ -0,0 +1,130 @@
+use color::{get_saved_color, save_color, Hsv, Rgb};
+use profile;
+
+/// Maximal chroma distance of a reading to a calibrated marker color.
+const MARKER_TOLERANCE: f32 = 0.2;
+/// Minimal saturation of a marker color. Unsaturated colors like black, white or the grey
+/// readings of the reflect mode lie close to the line and the background on the chroma disc.
+const MARKER_SATURATION: f32 = 0.35;
+/// Number of consecutive readings required to accept a marker.
+const MARKER_CONFIRMATION: u32 = 3;
+
+/// Colored patches on the track that trigger a behaviour of the line follower.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Marker {
+    /// Junction of multiple lines, for example a green patch.
+    Junction,
+    /// Stop the line follower, for example a red bar.
+    Stop,
+    /// Slow down for a while, for example a yellow bar.
+    Slow,
+    /// Start and finish line of a closed track.
+    Lap,
+}
+
+impl Marker {
+    pub fn from_id(id: u8) -> Option<Marker> {
+        match id {
+            0 => Some(Marker::Junction),
+            1 => Some(Marker::Stop),
+            2 => Some(Marker::Slow),
+            3 => Some(Marker::Lap),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            Marker::Junction => 0,
+            Marker::Stop => 1,
+            Marker::Slow => 2,
+            Marker::Lap => 3,
+        }
+    }
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Marker::Junction => "marker_junction",
+            Marker::Stop => "marker_stop",
+            Marker::Slow => "marker_slow",
+            Marker::Lap => "marker_lap",
+        }
+    }
+
+    pub fn all() -> Vec<Marker> {
+        vec![Marker::Junction, Marker::Stop, Marker::Slow, Marker::Lap]
+    }
+}
+
+/// Recognises calibrated marker colors in the stream of color readings.
+pub struct MarkerDetector {
+    colors: Vec<(Marker, Hsv)>,
+    candidate: Option<Marker>,
+    count: u32,
+}
+
+impl MarkerDetector {
+    /// Load all calibrated marker colors. Uncalibrated and unsaturated markers are never
+    /// detected.
+    pub fn load() -> MarkerDetector {
+        let colors = Marker::all()
+            .into_iter()
+            .filter(|marker| profile::path(marker.file_name()).exists())
+            .map(|marker| {
+                (
+                    marker,
+                    Hsv::from_rgb(&get_saved_color(profile::path(marker.file_name()), 0)),
+                )
+            })
+            .filter(|&(_, color)| color.saturation >= MARKER_SATURATION)
+            .collect();
+
+        MarkerDetector {
+            colors,
+            candidate: None,
+            count: 0,
+        }
+    }
+
+    pub fn calibrate(&mut self, marker: Marker, color: Rgb) {
+        if Hsv::from_rgb(&color).saturation < MARKER_SATURATION {
+            println!("Marker color {:?} is not saturated enough, ignored!", color);
+            return;
+        }
+
+        save_color(profile::path(marker.file_name()), &color);
+
+        self.colors.retain(|&(m, _)| m != marker);
+        self.colors.push((marker, Hsv::from_rgb(&color)));
+    }
+
+    fn classify(&self, sensor: &Rgb) -> Option<Marker> {
+        let sensor = Hsv::from_rgb(sensor);
+
+        self.colors
+            .iter()
+            .map(|&(marker, color)| (marker, sensor.chroma_distance(&color)))
+            .filter(|&(_, distance)| distance < MARKER_TOLERANCE)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(marker, _)| marker)
+    }
+
+    /// Feed the next color reading. Returns the marker once when it is entered.
+    pub fn update(&mut self, sensor: &Rgb) -> Option<Marker> {
+        let marker = self.classify(sensor);
+
+        if marker != self.candidate {
+            self.candidate = marker;
+            self.count = 0;
+        }
+
+        let candidate = self.candidate?;
+
+        self.count += 1;
+        if self.count == MARKER_CONFIRMATION {
+            Some(candidate)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use battery::{Battery, BatteryState};
+use driving::DrivingCommand;
+use ev3dev_lang_rust::{Ev3Result, Led};
+use network::NetworkCommand;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval between two battery samples.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+/// Interval between two battery telemetry messages.
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// Filtered voltage below which the battery is low.
+pub const WARNING_VOLTAGE: f32 = 6.8;
+/// Filtered voltage below which the brick is about to brown out.
+pub const CRITICAL_VOLTAGE: f32 = 6.3;
+/// Voltage rise above the warning threshold required to leave the warning state.
+const VOLTAGE_HYSTERESIS: f32 = 0.2;
+/// Speed limit while the battery is low.
+const WARNING_SPEED: f32 = 0.6;
+/// Time the leds stay on or off while blinking.
+const BLINK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Blinks the leds while the battery is low, independent of the connection to the server.
+struct BatteryLed {
+    led: Led,
+    /// Whether the leds are on, `None` while they are not blinking.
+    on: Option<bool>,
+    last_toggle: Instant,
+}
+
+impl BatteryLed {
+    fn new() -> Ev3Result<BatteryLed> {
+        Ok(BatteryLed {
+            led: Led::new()?,
+            on: None,
+            last_toggle: Instant::now(),
+        })
+    }
+
+    /// Toggle the leds if due. The leds are only written when they change. A low battery blinks
+    /// the left led amber, a critical battery blinks both leds red.
+    fn update(&mut self, state: BatteryState) -> Ev3Result<()> {
+        if state == BatteryState::Ok {
+            self.on = None;
+            return Ok(());
+        }
+
+        let on = match self.on {
+            Some(_) if self.last_toggle.elapsed() < BLINK_INTERVAL => return Ok(()),
+            Some(on) => !on,
+            None => true,
+        };
+        self.on = Some(on);
+        self.last_toggle = Instant::now();
+
+        match state {
+            BatteryState::Critical => {
+                let color = if on { Led::COLOR_RED } else { Led::COLOR_OFF };
+                self.led.set_left_color(color)?;
+                self.led.set_right_color(color)
+            }
+            _ => self
+                .led
+                .set_left_color(if on { Led::COLOR_AMBER } else { Led::COLOR_OFF }),
+        }
+    }
+}
+
+/// Next battery state for the filtered voltage. The critical state is kept, because the
+/// voltage recovers as soon as the motors are stopped.
+fn next_state(state: BatteryState, voltage: f32) -> BatteryState {
+    match state {
+        BatteryState::Critical => BatteryState::Critical,
+        _ if voltage < CRITICAL_VOLTAGE => BatteryState::Critical,
+        BatteryState::Ok if voltage < WARNING_VOLTAGE => BatteryState::Warning,
+        BatteryState::Warning if voltage > WARNING_VOLTAGE + VOLTAGE_HYSTERESIS => BatteryState::Ok,
+        state => state,
+    }
+}
+
+fn perform_monitor(
+    driving: &Sender<DrivingCommand>,
+    network: &Sender<NetworkCommand>,
+) -> Ev3Result<()> {
+    let mut battery = Battery::open()?;
+    let mut state = BatteryState::Ok;
+    let mut last_telemetry: Option<Instant> = None;
+    let mut led = BatteryLed::new()?;
+
+    loop {
+        let voltage = battery.update()?;
+        let current = battery.current()?;
+
+        let next = next_state(state, voltage);
+        let changed = next != state;
+        if changed {
+            println!("battery {:?} at {} V", next, voltage);
+            state = next;
+
+            match state {
+                BatteryState::Ok => {
+                    driving.send(DrivingCommand::SetSpeedCap(1.0)).unwrap();
+                }
+                BatteryState::Warning => {
+                    driving
+                        .send(DrivingCommand::SetSpeedCap(WARNING_SPEED))
+                        .unwrap();
+                }
+                BatteryState::Critical => {
+                    driving.send(DrivingCommand::SetSpeedCap(0.0)).unwrap();
+                    driving.send(DrivingCommand::EmergencyStop).unwrap();
+                }
+            }
+        }
+
+        let due = match last_telemetry {
+            Some(time) => time.elapsed() >= TELEMETRY_INTERVAL,
+            None => true,
+        };
+        if due || changed {
+            network
+                .send(NetworkCommand::Battery(voltage, current, state))
+                .unwrap();
+            last_telemetry = Some(Instant::now());
+        }
+
+        led.update(state)?;
+
+        thread::sleep(SAMPLE_INTERVAL);
+    }
+}
+
+pub fn start(driving: Sender<DrivingCommand>, network: Sender<NetworkCommand>) {
+    thread::Builder::new()
+        .name("Monitor".to_string())
+        .spawn(move || loop {
+            if let Err(e) = perform_monitor(&driving, &network) {
+                println!("A monitor error occurred, retry! {:?}", e);
+                thread::sleep(SAMPLE_INTERVAL);
+            }
+        })
+        .unwrap();
+}
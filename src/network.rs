@@ -1,7 +1,25 @@
+use battery::BatteryState;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use color::Rgb;
+use controller::Gains;
+use diagnostics::Report;
+use drivetrain::{DrivetrainKind, Motion};
+use driving::{get_saved_control_mode, ControlMode};
+use edge::Edge;
 use ev3dev_lang_rust::Ev3Result;
+use filter::FilterKind;
+use kinematics::WheelGeometry;
+use marker::Marker;
+use pid::{get_saved_background, get_saved_foreground, LineDetection};
+use profile;
+use recovery::RecoveryStrategy;
+use route::Branch;
+use sensor::{SensorLayout, SensorMode};
+use shaping::InputShaping;
+use slew::SlewLimits;
 use status::ConnectionState;
 use status::Status;
+use std::cmp::min;
 use std::io::Cursor;
 use std::io::Read;
 use std::net::SocketAddr;
@@ -11,13 +29,33 @@ use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
 use std::time::Duration;
 use std::time::SystemTime;
+use tune::{Tuning, TuningRule};
 use RobotCommand;
 
 const DISCOVERY_PORT: u16 = 7500;
 const PING_TIMEOUT: Duration = Duration::from_millis(100);
 const STOP_TIMEOUT: u32 = 300;
 const DISCONNECT_TIMEOUT: u32 = 5000;
-const BUFFER_SIZE: usize = 64;
+/// Size of the receive buffer. Datagrams that fill it may be truncated and are dropped.
+const BUFFER_SIZE: usize = 1024;
+
+/// Bytes of a gain schedule point: speed, proportional, integral and derivative gain.
+const GAIN_SCHEDULE_POINT_SIZE: usize = 16;
+
+fn read_color(cursor: &mut Cursor<&[u8]>) -> Ev3Result<Rgb> {
+    let r = cursor.read_u16::<BigEndian>()?;
+    let g = cursor.read_u16::<BigEndian>()?;
+    let b = cursor.read_u16::<BigEndian>()?;
+
+    Ok((i32::from(r), i32::from(g), i32::from(b)))
+}
+
+fn write_color(wtr: &mut Vec<u8>, color: &Rgb) {
+    for &channel in &[color.0, color.1, color.2] {
+        wtr.write_u16::<BigEndian>(channel.max(0).min(i32::from(u16::MAX)) as u16)
+            .unwrap();
+    }
+}
 
 /// Start an UDP discovery on given port to find the server's socket address.
 fn get_server_address(discover_port: u16) -> Ev3Result<SocketAddr> {
@@ -100,6 +138,58 @@ fn parse_message_v1(
             // SetBackground
             let _ = robot_sender.send(RobotCommand::SetBackground).unwrap();
         }
+        33 => {
+            // SetLineDetection
+            let detection = LineDetection::from_id(cursor.read_u8()?);
+            let _ = robot_sender
+                .send(RobotCommand::SetLineDetection(detection))
+                .unwrap();
+        }
+        34 => {
+            // SetMarker
+            if let Some(marker) = Marker::from_id(cursor.read_u8()?) {
+                let _ = robot_sender.send(RobotCommand::SetMarker(marker)).unwrap();
+            }
+        }
+        35 => {
+            // SetRoute
+            let mut branches = Vec::new();
+            while let Ok(id) = cursor.read_u8() {
+                if let Some(branch) = Branch::from_id(id) {
+                    branches.push(branch);
+                }
+            }
+            let _ = robot_sender.send(RobotCommand::SetRoute(branches)).unwrap();
+        }
+        36 => {
+            // SetRecovery
+            let recovery = RecoveryStrategy::from_id(cursor.read_u8()?);
+            let _ = robot_sender
+                .send(RobotCommand::SetRecovery(recovery))
+                .unwrap();
+        }
+        37 => {
+            // SetRate
+            let rate = cursor.read_u16::<BigEndian>()?;
+            let _ = robot_sender
+                .send(RobotCommand::SetRate(u32::from(rate)))
+                .unwrap();
+        }
+        38 => {
+            // SetSensorLayout
+            let layout = SensorLayout::from_id(cursor.read_u8()?);
+            let _ = robot_sender
+                .send(RobotCommand::SetSensorLayout(layout))
+                .unwrap();
+        }
+        39 => {
+            // SetSensorMode
+            let mode = SensorMode::from_id(cursor.read_u8()?);
+            let ambient_compensation = cursor.read_u8()?;
+            let _ = robot_sender
+                .send(RobotCommand::SetSensorMode(mode, ambient_compensation != 0))
+                .unwrap();
+        }
         40 => {
             // SetName
             let mut name = String::new();
@@ -112,6 +202,221 @@ fn parse_message_v1(
             cursor.read_to_string(&mut color)?;
             status.set_color(color)
         }
+        50 => {
+            // SetFilters
+            let mut filters = Vec::new();
+            while let Ok(id) = cursor.read_u8() {
+                let parameter = cursor.read_f32::<BigEndian>()?;
+                if let Some(filter) = FilterKind::from_id(id, parameter) {
+                    filters.push(filter);
+                }
+            }
+            let _ = robot_sender
+                .send(RobotCommand::SetFilters(filters))
+                .unwrap();
+        }
+        51 => {
+            // SetEdge
+            let edge = Edge::from_id(cursor.read_u8()?);
+            let _ = robot_sender.send(RobotCommand::SetEdge(edge)).unwrap();
+        }
+        52 => {
+            // ResetLaps
+            let _ = robot_sender.send(RobotCommand::ResetLaps).unwrap();
+        }
+        53 => {
+            // SetGains
+            let proportional = cursor.read_f32::<BigEndian>()?;
+            let integral = cursor.read_f32::<BigEndian>()?;
+            let derivative = cursor.read_f32::<BigEndian>()?;
+            let _ = robot_sender
+                .send(RobotCommand::SetGains(Gains::new(
+                    proportional,
+                    integral,
+                    derivative,
+                )))
+                .unwrap();
+        }
+        54 => {
+            // AutoTune
+            let rule = TuningRule::from_id(cursor.read_u8()?);
+            let _ = robot_sender.send(RobotCommand::AutoTune(rule)).unwrap();
+        }
+        55 => {
+            // SetGainSchedule
+            let length = cursor.get_ref().len() - cursor.position() as usize;
+            let count = length / GAIN_SCHEDULE_POINT_SIZE;
+            if count * GAIN_SCHEDULE_POINT_SIZE != length {
+                println!("Invalid gain schedule, dropped!");
+                return Ok(());
+            }
+
+            let mut points = Vec::with_capacity(count);
+            for _ in 0..count {
+                let speed = cursor.read_f32::<BigEndian>()?;
+                let proportional = cursor.read_f32::<BigEndian>()?;
+                let integral = cursor.read_f32::<BigEndian>()?;
+                let derivative = cursor.read_f32::<BigEndian>()?;
+                points.push((speed, Gains::new(proportional, integral, derivative)));
+            }
+            let _ = robot_sender
+                .send(RobotCommand::SetGainSchedule(points))
+                .unwrap();
+        }
+        56 => {
+            // CreateProfile
+            let mut name = String::new();
+            cursor.read_to_string(&mut name)?;
+            let _ = robot_sender
+                .send(RobotCommand::CreateProfile(name))
+                .unwrap();
+        }
+        57 => {
+            // SelectProfile
+            let mut name = String::new();
+            cursor.read_to_string(&mut name)?;
+            let _ = robot_sender
+                .send(RobotCommand::SelectProfile(name))
+                .unwrap();
+        }
+        58 => {
+            // DeleteProfile
+            let mut name = String::new();
+            cursor.read_to_string(&mut name)?;
+            let _ = robot_sender
+                .send(RobotCommand::DeleteProfile(name))
+                .unwrap();
+        }
+        59 => {
+            // ListProfiles
+            let _ = robot_sender.send(RobotCommand::ListProfiles).unwrap();
+        }
+        60 => {
+            // SetForegroundColor
+            let color = read_color(cursor)?;
+            let _ = robot_sender
+                .send(RobotCommand::SetForegroundColor(color))
+                .unwrap();
+        }
+        61 => {
+            // SetBackgroundColor
+            let color = read_color(cursor)?;
+            let _ = robot_sender
+                .send(RobotCommand::SetBackgroundColor(color))
+                .unwrap();
+        }
+        62 => {
+            // GetCalibration
+            let _ = robot_sender.send(RobotCommand::GetCalibration).unwrap();
+        }
+        63 => {
+            // SetControlMode
+            let id = cursor.read_u8()?;
+            let weight = cursor.read_f32::<BigEndian>()?;
+            let _ = robot_sender
+                .send(RobotCommand::SetControlMode(ControlMode::from_id(
+                    id, weight,
+                )))
+                .unwrap();
+        }
+        64 => {
+            // EmergencyStop
+            let _ = robot_sender.send(RobotCommand::EmergencyStop).unwrap();
+        }
+        65 => {
+            // SetSlewLimits
+            let left = SlewLimits::new(
+                cursor.read_f32::<BigEndian>()?,
+                cursor.read_f32::<BigEndian>()?,
+            );
+            let right = SlewLimits::new(
+                cursor.read_f32::<BigEndian>()?,
+                cursor.read_f32::<BigEndian>()?,
+            );
+            let _ = robot_sender
+                .send(RobotCommand::SetSlewLimits(left, right))
+                .unwrap();
+        }
+        66 => {
+            // SetArcade
+            let throttle = cursor.read_f32::<BigEndian>()?;
+            let steer = cursor.read_f32::<BigEndian>()?;
+            let _ = robot_sender
+                .send(RobotCommand::SetArcade(throttle, steer))
+                .unwrap();
+        }
+        67 => {
+            // SetVelocity
+            let velocity = cursor.read_f32::<BigEndian>()?;
+            let yaw_rate = cursor.read_f32::<BigEndian>()?;
+            let _ = robot_sender
+                .send(RobotCommand::SetVelocity(velocity, yaw_rate))
+                .unwrap();
+        }
+        68 => {
+            // SetInputShaping
+            let deadband = cursor.read_f32::<BigEndian>()?;
+            let expo = cursor.read_f32::<BigEndian>()?;
+            let _ = robot_sender
+                .send(RobotCommand::SetInputShaping(InputShaping::new(
+                    deadband, expo,
+                )))
+                .unwrap();
+        }
+        69 => {
+            // SetWheelGeometry
+            let track_width = cursor.read_f32::<BigEndian>()?;
+            let max_wheel_speed = cursor.read_f32::<BigEndian>()?;
+            let _ = robot_sender
+                .send(RobotCommand::SetWheelGeometry(WheelGeometry::new(
+                    track_width,
+                    max_wheel_speed,
+                )))
+                .unwrap();
+        }
+        70 => {
+            // SetDrivetrain
+            let kind = DrivetrainKind::from_id(cursor.read_u8()?);
+            let _ = robot_sender
+                .send(RobotCommand::SetDrivetrain(kind))
+                .unwrap();
+        }
+        71 => {
+            // SetMotion
+            let forward = cursor.read_f32::<BigEndian>()?;
+            let lateral = cursor.read_f32::<BigEndian>()?;
+            let rotation = cursor.read_f32::<BigEndian>()?;
+            let _ = robot_sender
+                .send(RobotCommand::SetMotion(Motion::new(
+                    forward, lateral, rotation,
+                )))
+                .unwrap();
+        }
+        72 => {
+            // SetNominalVoltage
+            let voltage = cursor.read_f32::<BigEndian>()?;
+            let _ = robot_sender
+                .send(RobotCommand::SetNominalVoltage(voltage))
+                .unwrap();
+        }
+        73 => {
+            // SetBackOff
+            let enabled = cursor.read_u8()? != 0;
+            let _ = robot_sender
+                .send(RobotCommand::SetBackOff(enabled))
+                .unwrap();
+        }
+        74 => {
+            // Diagnose
+            let _ = robot_sender.send(RobotCommand::Diagnose).unwrap();
+        }
+        75 => {
+            // SetStartupDiagnose
+            let enabled = cursor.read_u8()? != 0;
+            let _ = robot_sender
+                .send(RobotCommand::SetStartupDiagnose(enabled))
+                .unwrap();
+        }
         _ => {
             // Nothing to do
         }
@@ -119,6 +424,29 @@ fn parse_message_v1(
     Ok(())
 }
 
+/// Overall outcome and the number of checks, followed by the outcome, the name and the detail of
+/// every check. Texts are prefixed by their length.
+fn report_bytes(report: &Report) -> Vec<u8> {
+    let mut wtr = vec![report.outcome().id(), report.checks.len() as u8];
+    for check in &report.checks {
+        wtr.push(check.outcome.id());
+        for text in &[&check.name, &check.detail] {
+            let bytes = text.as_bytes();
+            let length = bytes.len().min(u8::MAX as usize);
+            wtr.push(length as u8);
+            wtr.extend_from_slice(&bytes[..length]);
+        }
+    }
+    wtr
+}
+
+fn control_mode_bytes(mode: ControlMode) -> Vec<u8> {
+    let (id, weight) = mode.id();
+    let mut wtr = vec![id];
+    wtr.write_f32::<BigEndian>(weight).unwrap();
+    wtr
+}
+
 fn send(
     socket: &UdpSocket,
     target: &SocketAddr,
@@ -182,6 +510,24 @@ fn perform_networking(
         4,
         status.get_available_colors().join(";").into_bytes(),
     )?;
+    send(
+        &socket,
+        &server_address,
+        1,
+        14,
+        profile::active().unwrap_or_default().into_bytes(),
+    )?;
+    let mut wtr = vec![];
+    write_color(&mut wtr, &get_saved_foreground());
+    write_color(&mut wtr, &get_saved_background());
+    send(&socket, &server_address, 1, 16, wtr)?;
+    send(
+        &socket,
+        &server_address,
+        1,
+        17,
+        control_mode_bytes(get_saved_control_mode()),
+    )?;
 
     let mut stopped = false;
 
@@ -192,22 +538,27 @@ fn perform_networking(
         match message {
             Ok((size, _)) => {
                 last_pong = SystemTime::now();
+                robot_sender.send(RobotCommand::Heartbeat).unwrap();
 
                 if stopped {
                     stopped = false;
                     status.set_connection_state(ConnectionState::Connected);
                 }
 
-                // Generate reader
-                let mut cursor = Cursor::new(&receive_buffer[..size]);
+                if size >= BUFFER_SIZE {
+                    println!("Message too long, dropped!");
+                } else {
+                    // Generate reader
+                    let mut cursor = Cursor::new(&receive_buffer[..size]);
 
-                let message_version = cursor.read_u8()?;
+                    let message_version = cursor.read_u8()?;
 
-                match message_version {
-                    1 => {
-                        let _ = parse_message_v1(&mut cursor, robot_sender, &mut status);
+                    match message_version {
+                        1 => {
+                            let _ = parse_message_v1(&mut cursor, robot_sender, &mut status);
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
             }
             Err(e) => {
@@ -229,15 +580,122 @@ fn perform_networking(
             }
         }
 
-        if let Ok(command) = stop_receiver.try_recv() {
+        while let Ok(command) = stop_receiver.try_recv() {
             match command {
                 NetworkCommand::Color(r, g, b) => {
                     send(&socket, &server_address, 1, 5, vec![r, g, b])?;
+                }
+                NetworkCommand::Battery(voltage, current, state) => {
+                    status.set_battery_state(state);
 
                     let mut wtr = vec![];
                     wtr.write_f32::<BigEndian>(status.get_power()).unwrap();
+                    wtr.write_f32::<BigEndian>(voltage).unwrap();
+                    wtr.write_f32::<BigEndian>(current).unwrap();
+                    wtr.write_u8(state.id()).unwrap();
                     send(&socket, &server_address, 1, 6, wtr)?;
                 }
+                NetworkCommand::FilteredColor(r, g, b) => {
+                    send(&socket, &server_address, 1, 11, vec![r, g, b])?;
+                }
+                NetworkCommand::Marker(marker) => {
+                    send(&socket, &server_address, 1, 7, vec![marker.id()])?;
+                }
+                NetworkCommand::Junction(index, branch) => {
+                    send(
+                        &socket,
+                        &server_address,
+                        1,
+                        8,
+                        vec![min(255, index) as u8, branch.id()],
+                    )?;
+                }
+                NetworkCommand::Lap(lap, time) => {
+                    let mut wtr = vec![];
+                    wtr.write_u16::<BigEndian>(min(u32::from(u16::MAX), lap) as u16)
+                        .unwrap();
+                    wtr.write_u32::<BigEndian>(time.as_secs() as u32 * 1000 + time.subsec_millis())
+                        .unwrap();
+                    send(&socket, &server_address, 1, 12, wtr)?;
+                }
+                NetworkCommand::Tuning(tuning) => {
+                    let mut wtr = vec![];
+                    if let Some(tuning) = tuning {
+                        wtr.write_u8(tuning.rule.id()).unwrap();
+                        wtr.write_f32::<BigEndian>(tuning.ultimate_gain).unwrap();
+                        wtr.write_f32::<BigEndian>(tuning.ultimate_period).unwrap();
+                        wtr.write_f32::<BigEndian>(tuning.gains.proportional)
+                            .unwrap();
+                        wtr.write_f32::<BigEndian>(tuning.gains.integral).unwrap();
+                        wtr.write_f32::<BigEndian>(tuning.gains.derivative).unwrap();
+                    }
+                    send(&socket, &server_address, 1, 13, wtr)?;
+                }
+                NetworkCommand::Profile(name) => {
+                    send(
+                        &socket,
+                        &server_address,
+                        1,
+                        14,
+                        name.unwrap_or_default().into_bytes(),
+                    )?;
+                }
+                NetworkCommand::Profiles(names) => {
+                    send(
+                        &socket,
+                        &server_address,
+                        1,
+                        15,
+                        names.join(";").into_bytes(),
+                    )?;
+                }
+                NetworkCommand::Calibration(foreground, background) => {
+                    let mut wtr = vec![];
+                    write_color(&mut wtr, &foreground);
+                    write_color(&mut wtr, &background);
+                    send(&socket, &server_address, 1, 16, wtr)?;
+                }
+                NetworkCommand::ControlMode(mode) => {
+                    send(&socket, &server_address, 1, 17, control_mode_bytes(mode))?;
+                }
+                NetworkCommand::Stall(motor, count) => {
+                    let mut wtr = vec![motor];
+                    wtr.write_u32::<BigEndian>(count).unwrap();
+                    send(&socket, &server_address, 1, 18, wtr)?;
+                }
+                NetworkCommand::GainSchedule(points) => {
+                    let mut wtr = vec![];
+                    for (speed, gains) in points {
+                        wtr.write_f32::<BigEndian>(speed).unwrap();
+                        wtr.write_f32::<BigEndian>(gains.proportional).unwrap();
+                        wtr.write_f32::<BigEndian>(gains.integral).unwrap();
+                        wtr.write_f32::<BigEndian>(gains.derivative).unwrap();
+                    }
+                    send(&socket, &server_address, 1, 23, wtr)?;
+                }
+                NetworkCommand::Drivetrain(kind) => {
+                    send(&socket, &server_address, 1, 22, vec![kind.id()])?;
+                }
+                NetworkCommand::Edge(edge) => {
+                    send(&socket, &server_address, 1, 24, vec![edge.id()])?;
+                }
+                NetworkCommand::Collision => {
+                    send(&socket, &server_address, 1, 19, vec![])?;
+                }
+                NetworkCommand::Slip(motor) => {
+                    send(&socket, &server_address, 1, 20, vec![motor])?;
+                }
+                NetworkCommand::Diagnostics(report) => {
+                    send(&socket, &server_address, 1, 21, report_bytes(&report))?;
+                }
+                NetworkCommand::LineLost => {
+                    send(&socket, &server_address, 1, 9, vec![])?;
+                }
+                NetworkCommand::Overrun(overruns) => {
+                    let mut wtr = vec![];
+                    wtr.write_u32::<BigEndian>(overruns).unwrap();
+                    send(&socket, &server_address, 1, 10, wtr)?;
+                }
                 NetworkCommand::Stop => {
                     return Ok(());
                 }
@@ -269,5 +727,24 @@ pub fn start(robot_sender: Sender<RobotCommand>) -> Sender<NetworkCommand> {
 #[allow(dead_code)]
 pub enum NetworkCommand {
     Color(u8, u8, u8),
+    FilteredColor(u8, u8, u8),
+    Battery(f32, f32, BatteryState),
+    Marker(Marker),
+    Junction(usize, Branch),
+    Lap(u32, Duration),
+    Tuning(Option<Tuning>),
+    Profile(Option<String>),
+    Profiles(Vec<String>),
+    Calibration(Rgb, Rgb),
+    ControlMode(ControlMode),
+    Stall(u8, u32),
+    Drivetrain(DrivetrainKind),
+    GainSchedule(Vec<(f32, Gains)>),
+    Edge(Edge),
+    Collision,
+    Slip(u8),
+    Diagnostics(Report),
+    LineLost,
+    Overrun(u32),
     Stop,
 }
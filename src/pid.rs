@@ -1,36 +1,122 @@
+use color::{get_saved_color, save_color, Hsv, Rgb};
+use controller::{AntiWindup, Controller, GainSchedule, Gains};
+use diagnostics::Check;
 use driving::DrivingCommand;
+use edge::{Edge, EdgeSwitch};
+use filter::FilterKind;
+use lap::{LapProfile, Section};
+use marker::{Marker, MarkerDetector};
+use profile;
+use recovery::{LineSearch, RecoveryStrategy};
+use route::{Branch, BranchManoeuvre, JunctionDetector, Route};
+use schedule::Scheduler;
+use sensor::{LineSensors, SensorLayout, SensorMode};
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
 
-use ev3dev_lang_rust::sensors::ColorSensor;
 use ev3dev_lang_rust::Ev3Result;
 use network::NetworkCommand;
 use std::cmp::min;
+use std::collections::VecDeque;
 use std::fs;
+use std::path::Path;
 use std::time::Duration;
+use tune::{AutoTune, TuningRule};
 
 const COLOR_TIMEOUT: Duration = Duration::from_millis(500);
+/// Number of readings of the color sensor self-test.
+const DIAGNOSTIC_SAMPLES: usize = 10;
+const DIAGNOSTIC_INTERVAL: Duration = Duration::from_millis(20);
+/// Time to wait before restarting after an error, e.g. when no color sensor is connected.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// Largest raw reading of a color channel.
+const RAW_MAXIMUM: i32 = 1020;
+/// Largest spread of the readings of a standing robot.
+const NOISE_MAXIMUM: i32 = 100;
+/// Smallest distance between the calibrated foreground and background colors.
+const CALIBRATION_DISTANCE: i32 = 30;
 
+const DEFAULT_RATE: u32 = 50;
+/// Slowest control loop rate that still feeds the drive watchdog in time.
+const MINIMUM_RATE: u32 = 5;
+
+// Gains in physical units: the integral is given in error seconds, the derivative in error per
+// second.
 const CONST_PROPORTIONAL: f32 = 0.4;
-const CONST_INTEGRAL: f32 = 0.18;
-const CONST_DERIVATIVE: f32 = 0.25;
-const INTEGRAL_MAXIMUM: f32 = 2.5;
-const INTEGRAL_LIMITER: f32 = (INTEGRAL_MAXIMUM - 1.0) / INTEGRAL_MAXIMUM;
+const CONST_INTEGRAL: f32 = 0.5;
+const CONST_DERIVATIVE: f32 = 0.005;
+/// Limit of the integral in error seconds, the integral term stays below 0.45.
+const INTEGRAL_MAXIMUM: f32 = 0.9;
+/// Integral below which the robot follows the line well enough to drive fast.
+const INTEGRAL_FAST: f32 = 0.07;
+/// Integral above which the robot is off the line for long enough to slow down.
+const INTEGRAL_SLOW: f32 = 0.36;
+/// Tracking gain of the back calculation anti windup.
+const ANTI_WINDUP_TRACKING: f32 = 1.0;
+/// Time constant of the derivative filter in seconds.
+const DERIVATIVE_FILTER: f32 = 0.02;
+/// Time constant in seconds of the speed used to look up the scheduled gains, so that the gains
+/// change smoothly between speed bands.
+const GAIN_SCHEDULE_FILTER: f32 = 0.2;
 const SPEED: f32 = 0.6;
 const SPEED_FAST: f32 = SPEED + 0.4;
 const SPEED_NORMAL: f32 = SPEED;
 const SPEED_SLOW: f32 = SPEED - 0.2;
 const COUNTERMEASURE: f32 = 0.5;
-const DRIVE_MULTIPLIER: f32 = -1.0;
+const OUTPUT_LIMIT: f32 = 1.0 / COUNTERMEASURE;
+/// Time in seconds to drive slowly after a slow marker.
+const MARKER_SLOW_TIME: f32 = 2.0;
+/// Time in seconds to drive slowly after a manoeuvre or a line search.
+const RECOVERY_SLOW_TIME: f32 = 0.4;
+/// Time in seconds a single sensor may be off the line before it is searched.
+const SINGLE_LOST_TIME: f32 = 0.3;
+/// Time in seconds without any of two sensors seeing the line before it is searched.
+const DUAL_LOST_TIME: f32 = 0.5;
 
-fn calc_error(
-    color_sensor: &mut ColorSensor,
-    foreground_color: &(i32, i32, i32),
-    background_color: &(i32, i32, i32),
-    network: &Sender<NetworkCommand>,
-) -> Ev3Result<f32> {
-    let sensor = color_sensor.get_rgb()?;
+const LINE_DETECTION_FILE: &str = "line_detection";
+const EDGE_FILE: &str = "edge";
+const RECOVERY_FILE: &str = "recovery";
+const SENSOR_LAYOUT_FILE: &str = "sensor_layout";
+const FILTERS_FILE: &str = "filters";
+const RATE_FILE: &str = "rate";
+/// Shared settings files of the line follower.
+pub const SETTINGS_FILES: [&str; 6] = [
+    LINE_DETECTION_FILE,
+    EDGE_FILE,
+    RECOVERY_FILE,
+    SENSOR_LAYOUT_FILE,
+    FILTERS_FILE,
+    RATE_FILE,
+];
+
+/// Strategy to calculate the line error from a color reading.
+#[derive(Clone, Copy)]
+pub enum LineDetection {
+    /// Normalized red, green and blue distance. Works best for dark lines on bright ground.
+    Rgb,
+    /// Hue and saturation distance. Works for colored lines on any ground.
+    Hue,
+}
+
+impl LineDetection {
+    pub fn from_id(id: u8) -> LineDetection {
+        match id {
+            1 => LineDetection::Hue,
+            _ => LineDetection::Rgb,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            LineDetection::Rgb => 0,
+            LineDetection::Hue => 1,
+        }
+    }
+}
+
+/// Position of the reading between foreground (0.0) and background (2.0) by rgb channels.
+fn calc_rgb_position(sensor: &Rgb, foreground_color: &Rgb, background_color: &Rgb) -> f32 {
     let red =
         (sensor.0 - foreground_color.0) as f32 / (background_color.0 - foreground_color.0) as f32;
     let green =
@@ -38,115 +124,522 @@ fn calc_error(
     let blue =
         (sensor.2 - foreground_color.2) as f32 / (background_color.2 - foreground_color.2) as f32;
 
+    (red + green + blue) / 1.5
+}
+
+/// Position of the reading between foreground (0.0) and background (2.0) by hue and saturation.
+fn calc_hue_position(sensor: &Rgb, foreground_color: &Rgb, background_color: &Rgb) -> f32 {
+    let sensor = Hsv::from_rgb(sensor);
+    let foreground = sensor.chroma_distance(&Hsv::from_rgb(foreground_color));
+    let background = sensor.chroma_distance(&Hsv::from_rgb(background_color));
+
+    if foreground + background <= 0.0 {
+        1.0
+    } else {
+        2.0 * foreground / (foreground + background)
+    }
+}
+
+fn calc_position(
+    sensor: &Rgb,
+    foreground_color: &Rgb,
+    background_color: &Rgb,
+    line_detection: LineDetection,
+) -> f32 {
+    let position = match line_detection {
+        LineDetection::Rgb => calc_rgb_position(sensor, foreground_color, background_color),
+        LineDetection::Hue => calc_hue_position(sensor, foreground_color, background_color),
+    };
+
+    position.min(2.0).max(0.0)
+}
+
+/// Position of one or two sensors relative to the line, between foreground (0.0) and
+/// background (2.0).
+struct LinePosition {
+    primary: f32,
+    secondary: Option<f32>,
+}
+
+impl LinePosition {
+    /// Line error for the controller. A single sensor follows the given edge, two sensors
+    /// follow the center of the line.
+    fn error(&self, edge: Edge) -> f32 {
+        match self.secondary {
+            Some(secondary) => (self.primary - secondary) / 2.0,
+            None => (self.primary - 1.0) * edge.multiplier(),
+        }
+    }
+
+    /// Position of the sensor nearest to the line, negative values lie on the line.
+    fn nearest(&self) -> f32 {
+        self.secondary.unwrap_or(self.primary).min(self.primary) - 1.0
+    }
+
+    /// Position of the sensor farthest from the line, negative values lie on the line.
+    fn farthest(&self) -> f32 {
+        self.secondary.unwrap_or(self.primary).max(self.primary) - 1.0
+    }
+}
+
+/// Read the current colors and forward the raw and filtered primary one to the server.
+fn read_colors(
+    sensors: &mut LineSensors,
+    network: &Sender<NetworkCommand>,
+) -> Ev3Result<(Rgb, Option<Rgb>)> {
+    let (sensor, secondary) = sensors.read()?;
+    let raw = sensors.raw();
+
     network
         .send(NetworkCommand::Color(
+            min(255, raw.0 / 2) as u8,
+            min(255, raw.1 / 2) as u8,
+            min(255, raw.2 / 2) as u8,
+        ))
+        .unwrap();
+    network
+        .send(NetworkCommand::FilteredColor(
             min(255, sensor.0 / 2) as u8,
             min(255, sensor.1 / 2) as u8,
             min(255, sensor.2 / 2) as u8,
         ))
         .unwrap();
 
-    Ok((((red + green + blue) / 1.5).min(2.0).max(0.0) - 1.0) * DRIVE_MULTIPLIER)
+    Ok((sensor, secondary))
+}
+
+/// Calibrated colors and detection settings of the line follower.
+struct Calibration {
+    foreground: Rgb,
+    background: Rgb,
+    line_detection: LineDetection,
+    edge: Edge,
+    recovery: RecoveryStrategy,
+    rate: u32,
+    gains: Gains,
+    gain_schedule: GainSchedule,
+    markers: MarkerDetector,
+}
+
+impl Calibration {
+    fn load() -> Calibration {
+        Calibration {
+            foreground: get_saved_foreground(),
+            background: get_saved_background(),
+            line_detection: get_saved_line_detection(),
+            edge: get_saved_edge(),
+            recovery: get_saved_recovery(),
+            rate: get_saved_rate(),
+            gains: get_saved_gains(),
+            gain_schedule: get_saved_gain_schedule(),
+            markers: MarkerDetector::load(),
+        }
+    }
+
+    /// Gains at the given speed. Without a gain schedule the same gains are used at all speeds.
+    fn gains(&self, speed: f32) -> Gains {
+        self.gain_schedule.gains(speed).unwrap_or(self.gains)
+    }
+
+    /// Send the calibrated colors to the server.
+    fn report(&self, network: &Sender<NetworkCommand>) {
+        network
+            .send(NetworkCommand::Calibration(
+                self.foreground,
+                self.background,
+            ))
+            .unwrap();
+    }
+
+    fn position(&self, sensor: &Rgb) -> f32 {
+        calc_position(
+            sensor,
+            &self.foreground,
+            &self.background,
+            self.line_detection,
+        )
+    }
+
+    /// Apply a calibration command. Returns the command if it does not change the calibration.
+    fn apply(
+        &mut self,
+        command: PidCommand,
+        sensors: &mut LineSensors,
+        network: &Sender<NetworkCommand>,
+    ) -> Ev3Result<Option<PidCommand>> {
+        match command {
+            PidCommand::SetForeground => {
+                self.foreground = sensors.read()?.0;
+                save_foreground(&self.foreground);
+            }
+            PidCommand::SetBackground => {
+                self.background = sensors.read()?.0;
+                save_background(&self.background);
+            }
+            PidCommand::SetForegroundColor(color) => {
+                self.foreground = color;
+                save_foreground(&self.foreground);
+            }
+            PidCommand::SetBackgroundColor(color) => {
+                self.background = color;
+                save_background(&self.background);
+            }
+            PidCommand::SetLineDetection(detection) => {
+                self.line_detection = detection;
+                save_line_detection(detection);
+            }
+            PidCommand::SetRecovery(recovery) => {
+                self.recovery = recovery;
+                save_recovery(recovery);
+            }
+            PidCommand::SetRate(rate) => {
+                self.rate = rate.max(MINIMUM_RATE);
+                save_rate(self.rate);
+            }
+            PidCommand::SetGains(gains) => {
+                self.gains = gains;
+                save_gains(&gains);
+            }
+            PidCommand::SetGainSchedule(points) => {
+                self.gain_schedule = GainSchedule::new(points);
+                save_gain_schedule(&self.gain_schedule);
+                network
+                    .send(NetworkCommand::GainSchedule(
+                        self.gain_schedule.points().to_vec(),
+                    ))
+                    .unwrap();
+            }
+            PidCommand::SetMarker(marker) => {
+                self.markers.calibrate(marker, sensors.read()?.0);
+            }
+            command => return Ok(Some(command)),
+        }
+
+        Ok(None)
+    }
 }
 
 fn run(
     pid_receiver: &Receiver<PidCommand>,
     driving_sender: &Sender<DrivingCommand>,
-    color_sensor: &mut ColorSensor,
-    foreground_color: &mut (i32, i32, i32),
-    background_color: &mut (i32, i32, i32),
+    sensors: &mut LineSensors,
+    calibration: &mut Calibration,
+    route: &mut Route,
+    laps: &mut LapProfile,
     network: &Sender<NetworkCommand>,
 ) -> Ev3Result<()> {
     let mut history_error: f32 = 0.0;
     let mut last_error: f32 = 0.0;
-    let mut integral: f32 = 0.0;
-    let dt: f32 = 1.0;
-    let mut lost_line = 0;
-    let mut drive_slow = 0;
+    let mut scheduled_speed = SPEED_NORMAL;
+    let mut controller = Controller::new(calibration.gains(scheduled_speed))
+        .with_output_limits(-OUTPUT_LIMIT, OUTPUT_LIMIT)
+        .with_anti_windup(AntiWindup::BackCalculation(ANTI_WINDUP_TRACKING))
+        .with_derivative_filter(DERIVATIVE_FILTER)
+        .with_integral_limit(INTEGRAL_MAXIMUM);
+    // Time in seconds the line is lost
+    let mut lost_line: f32 = 0.0;
+    let mut line_side: f32 = calibration.edge.multiplier();
+    let mut drive_slow: f32 = 0.0;
+    let mut marker_slow: f32 = 0.0;
+    let mut junctions = JunctionDetector::new();
+    let mut manoeuvre: Option<BranchManoeuvre> = None;
+    let mut edge_switch: Option<EdgeSwitch> = None;
+    let mut search: Option<LineSearch> = None;
+    let mut scheduler = Scheduler::new(calibration.rate);
+
+    route.reset();
+    laps.reset();
 
     loop {
+        let dt = scheduler.wait();
+
+        if let Some(overruns) = scheduler.report() {
+            println!("{} control loop overruns", overruns);
+            network.send(NetworkCommand::Overrun(overruns)).unwrap();
+        }
+
         if let Ok(recv) = pid_receiver.try_recv() {
-            match recv {
-                PidCommand::Start => {
+            let command = match calibration.apply(recv, sensors, network)? {
+                Some(command) => apply_profile(command, calibration, sensors, network)?,
+                None => None,
+            };
+            match command {
+                Some(PidCommand::Stop) => {
+                    break;
+                }
+                Some(PidCommand::AutoTune(_)) => {
+                    println!("auto tune rejected, the line follower is running");
+                    network.send(NetworkCommand::Tuning(None)).unwrap();
+                }
+                Some(PidCommand::Diagnose(reply)) => {
+                    let _ = reply.send(vec![Check::warning(
+                        "color sensors",
+                        "skipped, the line follower is running".to_string(),
+                    )]);
+                }
+                Some(PidCommand::SetRoute(branches)) => {
+                    route.set(branches);
+                }
+                Some(PidCommand::ResetLaps) => {
+                    laps.clear();
+                }
+                Some(PidCommand::GetCalibration) => {
+                    calibration.report(network);
+                }
+                Some(PidCommand::SetEdge(edge))
+                    if edge != calibration.edge && edge_switch.is_none() =>
+                {
+                    edge_switch = Some(EdgeSwitch::new(calibration.edge));
+                }
+                Some(PidCommand::SetSensorLayout(layout)) => {
+                    if let Some(opened) =
+                        try_open_sensors(layout, get_saved_sensor_mode(), &get_saved_filters())
+                    {
+                        save_sensor_layout(layout);
+                        *sensors = opened;
+                        controller.reset();
+                    }
+                }
+                Some(PidCommand::SetSensorMode(mode, ambient_compensation)) => {
+                    if let Some(opened) = try_open_sensors(
+                        get_saved_sensor_layout(),
+                        (mode, ambient_compensation),
+                        &get_saved_filters(),
+                    ) {
+                        save_sensor_mode(mode, ambient_compensation);
+                        *sensors = opened;
+                        controller.reset();
+                    }
+                }
+                Some(PidCommand::SetFilters(filters)) => {
+                    if let Some(opened) = try_open_sensors(
+                        get_saved_sensor_layout(),
+                        get_saved_sensor_mode(),
+                        &filters,
+                    ) {
+                        save_filters(&filters);
+                        *sensors = opened;
+                    }
+                }
+                _ => {
                     //Do nothing
                 }
-                PidCommand::Stop => {
+            }
+            scheduler.set_rate(calibration.rate);
+        }
+
+        let (sensor, secondary) = read_colors(sensors, network)?;
+
+        let mut junction = false;
+
+        if let Some(marker) = calibration.markers.update(&sensor) {
+            network.send(NetworkCommand::Marker(marker)).unwrap();
+
+            match marker {
+                Marker::Stop => {
                     break;
                 }
-                PidCommand::SetForeground => {
-                    let (r, g, b) = color_sensor.get_rgb()?;
-                    foreground_color.0 = r;
-                    foreground_color.1 = g;
-                    foreground_color.2 = b;
-                    save_foreground(foreground_color);
+                Marker::Slow => {
+                    marker_slow = MARKER_SLOW_TIME;
                 }
-                PidCommand::SetBackground => {
-                    let (r, g, b) = color_sensor.get_rgb()?;
-                    background_color.0 = r;
-                    background_color.1 = g;
-                    background_color.2 = b;
-                    save_background(background_color);
+                Marker::Junction => {
+                    junction = true;
+                }
+                Marker::Lap => {
+                    if let Some((lap, time)) = laps.pass_start() {
+                        network.send(NetworkCommand::Lap(lap, time)).unwrap();
+                    }
                 }
             }
         }
 
-        let error = calc_error(color_sensor, foreground_color, background_color, network)?;
+        let position = LinePosition {
+            primary: calibration.position(&sensor),
+            secondary: secondary.map(|secondary| calibration.position(&secondary)),
+        };
+        let error = position.error(calibration.edge);
+        let multiplier = calibration.edge.multiplier();
+        let on_line = position.nearest() < -0.5;
 
-        integral = (integral + error * dt) * INTEGRAL_LIMITER;
-        let derivative = (error - last_error) / dt;
+        // A single sensor following an edge is fully on the line in sharp curves as well, so
+        // only rely on the signal when two sensors follow the line or a route is set.
+        let signal_junctions = position.secondary.is_some() || !route.is_empty();
+        if signal_junctions && junctions.update(position.farthest(), dt) {
+            junction = true;
+        }
 
-        let output =
-            CONST_PROPORTIONAL * error + CONST_INTEGRAL * integral + CONST_DERIVATIVE * derivative;
+        if junction && manoeuvre.is_none() && edge_switch.is_none() {
+            let (index, branch) = route.next();
+            network
+                .send(NetworkCommand::Junction(index, branch))
+                .unwrap();
+            if !route.is_empty() {
+                manoeuvre = Some(BranchManoeuvre::new(branch));
+            }
+        }
 
-        if lost_line > 15 {
-            println!("search line");
-            integral = INTEGRAL_MAXIMUM * DRIVE_MULTIPLIER;
-            history_error = 0.0;
-            last_error = 0.0;
-            lost_line = 0;
+        if let Some(ref mut current) = manoeuvre {
+            if let Some((left, right)) = current.update(on_line, SPEED_SLOW, dt) {
+                driving_sender
+                    .send(DrivingCommand::SetPid(left, right))
+                    .unwrap();
+                continue;
+            }
 
-            while calc_error(color_sensor, foreground_color, background_color, network)?
-                * DRIVE_MULTIPLIER
-                > -0.5
-            {
+            if current.timed_out() {
+                println!("line lost");
+                network.send(NetworkCommand::LineLost).unwrap();
+                break;
+            }
+        }
+
+        if manoeuvre.is_some() {
+            manoeuvre = None;
+            controller.reset();
+            history_error = error;
+            last_error = error;
+            lost_line = 0.0;
+            drive_slow = RECOVERY_SLOW_TIME;
+            junctions.reset();
+        }
+
+        if position.secondary.is_some() && edge_switch.is_some() {
+            // Two sensors follow the center, there is no edge to cross to
+            calibration.edge = edge_switch.take().unwrap().target();
+            save_edge(calibration.edge);
+            network
+                .send(NetworkCommand::Edge(calibration.edge))
+                .unwrap();
+        }
+
+        if let Some(ref mut current) = edge_switch {
+            if let Some((left, right)) = current.update(position.primary - 1.0, SPEED_SLOW, dt) {
                 driving_sender
-                    .send(DrivingCommand::SetPid(
-                        SPEED_SLOW * DRIVE_MULTIPLIER,
-                        -SPEED_SLOW * DRIVE_MULTIPLIER,
-                    ))
+                    .send(DrivingCommand::SetPid(left, right))
                     .unwrap();
+                continue;
             }
+        }
 
-            drive_slow = 20;
+        if let Some(current) = edge_switch.take() {
+            if current.timed_out() {
+                println!("edge switch failed");
+            } else {
+                calibration.edge = current.target();
+                save_edge(calibration.edge);
+            }
+            network
+                .send(NetworkCommand::Edge(calibration.edge))
+                .unwrap();
+            controller.reset();
+            history_error = 0.0;
+            last_error = 0.0;
+            lost_line = 0.0;
+            line_side = calibration.edge.multiplier();
+            drive_slow = RECOVERY_SLOW_TIME;
+            junctions.reset();
             continue;
         }
 
-        if lost_line > 0 {
-            if error * DRIVE_MULTIPLIER > 0.5 {
-                lost_line += 1;
+        if let Some(ref mut current) = search {
+            if !on_line {
+                match current.update(SPEED_SLOW, dt) {
+                    Some((left, right)) => {
+                        driving_sender
+                            .send(DrivingCommand::SetPid(left, right))
+                            .unwrap();
+                        continue;
+                    }
+                    None => {
+                        println!("line lost");
+                        network.send(NetworkCommand::LineLost).unwrap();
+                        break;
+                    }
+                }
+            }
+        }
+
+        if search.is_some() {
+            search = None;
+            drive_slow = RECOVERY_SLOW_TIME;
+        }
+
+        controller.set_gains(calibration.gains(scheduled_speed));
+        let output = controller.update(error, dt);
+        let integral = controller.integral();
+        let derivative = controller.derivative();
+
+        let lost = if position.secondary.is_some() {
+            // Two sensors straddle the line, so both of them seeing background is normal. The
+            // line counts as lost if none of them has seen it for a while, it is then searched
+            // on the side it was last seen on.
+            if position.nearest() < 0.5 {
+                lost_line = 0.0;
+                if error.abs() > 0.1 {
+                    line_side = error;
+                }
             } else {
-                lost_line = 0;
+                lost_line += dt;
             }
+            lost_line > DUAL_LOST_TIME && drive_slow <= 0.0
+        } else {
+            line_side = multiplier;
+            lost_line > SINGLE_LOST_TIME
+        };
+
+        if lost {
+            println!("search line");
+            controller.reset();
+            controller.set_integral(INTEGRAL_MAXIMUM * line_side.signum());
+            history_error = 0.0;
+            last_error = 0.0;
+            lost_line = 0.0;
+
+            search = Some(LineSearch::new(calibration.recovery, line_side));
+            continue;
         }
 
-        if (history_error - error).abs() > 0.7 && error * DRIVE_MULTIPLIER > 0.5 && drive_slow == 0
-        {
-            lost_line += 1;
+        if position.secondary.is_none() {
+            if lost_line > 0.0 {
+                if error * multiplier > 0.5 {
+                    lost_line += dt;
+                } else {
+                    lost_line = 0.0;
+                }
+            }
+
+            if (history_error - error).abs() > 0.7 && error * multiplier > 0.5 && drive_slow <= 0.0
+            {
+                lost_line += dt;
+            }
         }
 
         history_error = last_error;
         last_error = error;
 
         let mut speed = SPEED_NORMAL;
-        if drive_slow > 0 {
-            drive_slow -= 1;
+        if drive_slow > 0.0 {
+            drive_slow -= dt;
             speed = SPEED_SLOW;
-        } else if integral.abs() < 0.2 && derivative.abs() < 0.2 {
+        } else if let Some(section) = laps.section() {
+            speed = match section {
+                Section::Curve => SPEED_SLOW,
+                Section::Straight => SPEED_FAST,
+            };
+        } else if integral.abs() < INTEGRAL_FAST && derivative.abs() < 10.0 {
             speed = SPEED_FAST;
-        } else if integral.abs() > 1.0 {
+        } else if integral.abs() > INTEGRAL_SLOW {
             speed = SPEED_SLOW;
         }
 
+        if marker_slow > 0.0 {
+            marker_slow -= dt;
+            speed = speed.min(SPEED_SLOW);
+        }
+
+        laps.update(output, speed, dt);
+        scheduled_speed += (speed - scheduled_speed) * (dt / GAIN_SCHEDULE_FILTER).min(1.0);
+
         //println!("e: {} | o: {} | p: {} | i: {} | d: {}", error, output, error * CONST_PROPORTIONAL, integral * CONST_INTEGRAL, derivative * CONST_DERIVATIVE);
 
         driving_sender
@@ -164,56 +657,308 @@ fn run(
     Ok(())
 }
 
+/// Let the robot oscillate around the line with a relay and derive new gains from the
+/// oscillation. The gains are saved and reported to the server. Calibration and profile
+/// commands are applied at once, all other commands are returned to be handled afterwards.
+fn auto_tune(
+    pid_receiver: &Receiver<PidCommand>,
+    driving_sender: &Sender<DrivingCommand>,
+    sensors: &mut LineSensors,
+    calibration: &mut Calibration,
+    rule: TuningRule,
+    network: &Sender<NetworkCommand>,
+) -> Ev3Result<Vec<PidCommand>> {
+    let mut tune = AutoTune::new(rule);
+    let mut scheduler = Scheduler::new(calibration.rate);
+    let mut stopped = false;
+    let mut deferred = Vec::new();
+
+    loop {
+        let dt = scheduler.wait();
+
+        if let Ok(recv) = pid_receiver.try_recv() {
+            let command = match calibration.apply(recv, sensors, network)? {
+                Some(command) => apply_profile(command, calibration, sensors, network)?,
+                None => None,
+            };
+            match command {
+                Some(PidCommand::Stop) => {
+                    stopped = true;
+                    break;
+                }
+                Some(command) => deferred.push(command),
+                None => {}
+            }
+            scheduler.set_rate(calibration.rate);
+        }
+
+        let (sensor, secondary) = read_colors(sensors, network)?;
+        let position = LinePosition {
+            primary: calibration.position(&sensor),
+            secondary: secondary.map(|secondary| calibration.position(&secondary)),
+        };
+
+        match tune.update(position.error(calibration.edge), dt) {
+            Some(output) => {
+                driving_sender
+                    .send(DrivingCommand::SetPid(
+                        SPEED_SLOW + (COUNTERMEASURE * output),
+                        SPEED_SLOW - (COUNTERMEASURE * output),
+                    ))
+                    .unwrap();
+            }
+            None => break,
+        }
+    }
+
+    driving_sender
+        .send(DrivingCommand::SetPid(0.0, 0.0))
+        .unwrap();
+
+    if stopped {
+        return Ok(deferred);
+    }
+
+    let result = tune.result();
+    match result {
+        Some(tuning) => {
+            println!(
+                "auto tune: ku {} | pu {} | {:?}",
+                tuning.ultimate_gain, tuning.ultimate_period, tuning.gains
+            );
+            calibration.gains = tuning.gains;
+            save_gains(&tuning.gains);
+        }
+        None => {
+            println!("auto tune failed");
+        }
+    }
+    network.send(NetworkCommand::Tuning(result)).unwrap();
+
+    Ok(deferred)
+}
+
+/// Create, select, delete or list the calibration profiles and report them to the server. A
+/// changed profile is loaded at once. Returns the command if it does not manage the profiles.
+fn apply_profile(
+    command: PidCommand,
+    calibration: &mut Calibration,
+    sensors: &mut LineSensors,
+    network: &Sender<NetworkCommand>,
+) -> Ev3Result<Option<PidCommand>> {
+    let result = match command {
+        PidCommand::CreateProfile(name) => profile::create(&name),
+        PidCommand::SelectProfile(name) => profile::select(&name),
+        PidCommand::DeleteProfile(name) => profile::delete(&name),
+        PidCommand::ListProfiles => {
+            network
+                .send(NetworkCommand::Profiles(profile::list()))
+                .unwrap();
+            return Ok(None);
+        }
+        command => return Ok(Some(command)),
+    };
+
+    if let Err(e) = result {
+        println!("A profile error occurred! {:?}", e);
+    }
+
+    *calibration = Calibration::load();
+    *sensors = open_sensors()?;
+
+    network
+        .send(NetworkCommand::Profile(profile::active()))
+        .unwrap();
+    network
+        .send(NetworkCommand::Profiles(profile::list()))
+        .unwrap();
+
+    Ok(None)
+}
+
+/// Check the readings of one color sensor for a dark, saturated or noisy sensor.
+fn check_readings(name: &str, readings: &[Rgb]) -> Check {
+    let mut minimum = [i32::MAX; 3];
+    let mut maximum = [0; 3];
+    for reading in readings {
+        for (channel, &value) in [reading.0, reading.1, reading.2].iter().enumerate() {
+            minimum[channel] = min(minimum[channel], value);
+            maximum[channel] = maximum[channel].max(value);
+        }
+    }
+
+    let brightest = *maximum.iter().max().unwrap();
+    let spread = (0..3)
+        .map(|channel| maximum[channel] - minimum[channel])
+        .max()
+        .unwrap();
+
+    let detail = format!(
+        "{:?}, spread {}",
+        readings.last().unwrap_or(&(0, 0, 0)),
+        spread
+    );
+    if brightest == 0 {
+        Check::fail(name, format!("{}, no light", detail))
+    } else if brightest >= RAW_MAXIMUM {
+        Check::warning(name, format!("{}, saturated", detail))
+    } else if spread > NOISE_MAXIMUM {
+        Check::warning(name, format!("{}, noisy", detail))
+    } else {
+        Check::pass(name, detail)
+    }
+}
+
+/// Self-test of the color sensors and their calibration. The robot must stand still.
+fn check_sensors(sensors: &mut LineSensors, calibration: &Calibration) -> Vec<Check> {
+    let mut primary = Vec::new();
+    let mut secondary = Vec::new();
+    for _ in 0..DIAGNOSTIC_SAMPLES {
+        match sensors.read() {
+            Ok((reading, other)) => {
+                primary.push(reading);
+                if let Some(other) = other {
+                    secondary.push(other);
+                }
+            }
+            Err(e) => return vec![Check::fail("color sensors", format!("{:?}", e))],
+        }
+        thread::sleep(DIAGNOSTIC_INTERVAL);
+    }
+
+    let mut checks = vec![check_readings("color sensor 1", &primary)];
+    if !secondary.is_empty() {
+        checks.push(check_readings("color sensor 2", &secondary));
+    }
+
+    let (foreground, background) = (calibration.foreground, calibration.background);
+    let distance = (foreground.0 - background.0).abs()
+        + (foreground.1 - background.1).abs()
+        + (foreground.2 - background.2).abs();
+    let detail = format!("foreground {:?}, background {:?}", foreground, background);
+    checks.push(if distance < CALIBRATION_DISTANCE {
+        Check::warning("calibration", format!("{}, too similar", detail))
+    } else {
+        Check::pass("calibration", detail)
+    });
+
+    checks
+}
+
 fn perform_pid(
     pid_receiver: &Receiver<PidCommand>,
     driving_sender: &Sender<DrivingCommand>,
     network: &Sender<NetworkCommand>,
 ) -> Ev3Result<()> {
-    let mut color_sensor = ColorSensor::find().unwrap();
-    color_sensor.set_mode_rgb_raw()?;
+    let mut sensors = open_sensors()?;
 
-    let mut foreground_color = get_saved_foreground();
-    let mut background_color = get_saved_background();
+    let mut calibration = Calibration::load();
+    let mut route = Route::new();
+    let mut laps = LapProfile::new();
+    // Commands received during the auto tuning, handled before any new ones
+    let mut deferred: VecDeque<PidCommand> = VecDeque::new();
 
-    //println!("Current color: {:?}", color_sensor.get_rgb());
-    //println!("Foreground color: {:?}",foreground_color);
-    //println!("Background color: {:?}", background_color);
+    //println!("Current color: {:?}", sensors.read());
+    //println!("Foreground color: {:?}", calibration.foreground);
+    //println!("Background color: {:?}", calibration.background);
 
     loop {
-        if let Ok(command) = pid_receiver.recv_timeout(COLOR_TIMEOUT) {
+        let received = match deferred.pop_front() {
+            Some(command) => Some(command),
+            None => pid_receiver.recv_timeout(COLOR_TIMEOUT).ok(),
+        };
+        if let Some(command) = received {
+            let command = match calibration.apply(command, &mut sensors, network)? {
+                Some(command) => apply_profile(command, &mut calibration, &mut sensors, network)?,
+                None => None,
+            };
             match command {
-                PidCommand::Start => {
-                    run(
+                Some(PidCommand::Start) => {
+                    driving_sender
+                        .send(DrivingCommand::SetPidActive(true))
+                        .unwrap();
+                    let result = run(
                         pid_receiver,
                         driving_sender,
-                        &mut color_sensor,
-                        &mut foreground_color,
-                        &mut background_color,
+                        &mut sensors,
+                        &mut calibration,
+                        &mut route,
+                        &mut laps,
                         network,
-                    )?;
+                    );
+                    driving_sender
+                        .send(DrivingCommand::SetPidActive(false))
+                        .unwrap();
+                    result?;
                 }
-                PidCommand::Stop => {
-                    // Do nothing
+                Some(PidCommand::AutoTune(rule)) => {
+                    driving_sender
+                        .send(DrivingCommand::SetPidActive(true))
+                        .unwrap();
+                    let result = auto_tune(
+                        pid_receiver,
+                        driving_sender,
+                        &mut sensors,
+                        &mut calibration,
+                        rule,
+                        network,
+                    );
+                    driving_sender
+                        .send(DrivingCommand::SetPidActive(false))
+                        .unwrap();
+                    deferred.extend(result?);
+                }
+                Some(PidCommand::SetRoute(branches)) => {
+                    route.set(branches);
+                }
+                Some(PidCommand::ResetLaps) => {
+                    laps.clear();
+                }
+                Some(PidCommand::GetCalibration) => {
+                    calibration.report(network);
+                }
+                Some(PidCommand::Diagnose(reply)) => {
+                    let _ = reply.send(check_sensors(&mut sensors, &calibration));
                 }
-                PidCommand::SetForeground => {
-                    foreground_color = color_sensor.get_rgb()?;
-                    save_foreground(&foreground_color);
+                Some(PidCommand::SetEdge(edge)) => {
+                    calibration.edge = edge;
+                    save_edge(edge);
                 }
-                PidCommand::SetBackground => {
-                    background_color = color_sensor.get_rgb()?;
-                    save_background(&background_color);
+                Some(PidCommand::SetSensorLayout(layout)) => {
+                    if let Some(opened) =
+                        try_open_sensors(layout, get_saved_sensor_mode(), &get_saved_filters())
+                    {
+                        save_sensor_layout(layout);
+                        sensors = opened;
+                    }
+                }
+                Some(PidCommand::SetSensorMode(mode, ambient_compensation)) => {
+                    if let Some(opened) = try_open_sensors(
+                        get_saved_sensor_layout(),
+                        (mode, ambient_compensation),
+                        &get_saved_filters(),
+                    ) {
+                        save_sensor_mode(mode, ambient_compensation);
+                        sensors = opened;
+                    }
+                }
+                Some(PidCommand::SetFilters(filters)) => {
+                    if let Some(opened) = try_open_sensors(
+                        get_saved_sensor_layout(),
+                        get_saved_sensor_mode(),
+                        &filters,
+                    ) {
+                        save_filters(&filters);
+                        sensors = opened;
+                    }
+                }
+                _ => {
+                    // Do nothing
                 }
             }
         }
 
-        let sensor = color_sensor.get_rgb()?;
-        network
-            .send(NetworkCommand::Color(
-                min(255, sensor.0 / 2) as u8,
-                min(255, sensor.1 / 2) as u8,
-                min(255, sensor.2 / 2) as u8,
-            ))
-            .unwrap();
+        read_colors(&mut sensors, network)?;
     }
 }
 
@@ -230,8 +975,9 @@ pub fn start(
                 Ok(_) => {
                     break;
                 }
-                _ => {
-                    println!("A pid error occurred, retry!");
+                Err(e) => {
+                    println!("A pid error occurred, retry! {:?}", e);
+                    thread::sleep(RETRY_INTERVAL);
                 }
             }
         })
@@ -240,42 +986,191 @@ pub fn start(
     pid_sender
 }
 
-fn get_saved_color(name: &str, default: i32) -> (i32, i32, i32) {
-    let file = String::from(
-        fs::read_to_string(name)
-            .unwrap_or_else(|_| String::from("0;0;0"))
-            .trim(),
-    );
-    let vec: Vec<&str> = file.split(';').collect::<Vec<&str>>();
+pub fn get_saved_foreground() -> Rgb {
+    get_saved_color(profile::path("foreground"), 20)
+}
+fn save_foreground(color: &Rgb) {
+    save_color(profile::path("foreground"), color)
+}
+pub fn get_saved_background() -> Rgb {
+    get_saved_color(profile::path("background"), 200)
+}
+fn save_background(color: &Rgb) {
+    save_color(profile::path("background"), color)
+}
+fn get_saved_id(name: &str) -> u8 {
+    fs::read_to_string(name)
+        .unwrap_or_else(|_| String::new())
+        .trim()
+        .parse::<u8>()
+        .unwrap_or(0)
+}
+fn save_id(name: &str, id: u8) {
+    fs::write(name, id.to_string()).unwrap();
+}
 
-    let r = vec[0].parse::<i32>().unwrap_or(default);
-    let g = vec[1].parse::<i32>().unwrap_or(default);
-    let b = vec[2].parse::<i32>().unwrap_or(default);
+fn get_saved_line_detection() -> LineDetection {
+    LineDetection::from_id(get_saved_id(LINE_DETECTION_FILE))
+}
+fn save_line_detection(line_detection: LineDetection) {
+    save_id(LINE_DETECTION_FILE, line_detection.id())
+}
+fn get_saved_edge() -> Edge {
+    if Path::new(EDGE_FILE).exists() {
+        Edge::from_id(get_saved_id(EDGE_FILE))
+    } else {
+        Edge::Right
+    }
+}
+fn save_edge(edge: Edge) {
+    save_id(EDGE_FILE, edge.id())
+}
+fn get_saved_recovery() -> RecoveryStrategy {
+    RecoveryStrategy::from_id(get_saved_id(RECOVERY_FILE))
+}
+fn save_recovery(recovery: RecoveryStrategy) {
+    save_id(RECOVERY_FILE, recovery.id())
+}
+fn get_saved_sensor_layout() -> SensorLayout {
+    SensorLayout::from_id(get_saved_id(SENSOR_LAYOUT_FILE))
+}
+fn save_sensor_layout(layout: SensorLayout) {
+    save_id(SENSOR_LAYOUT_FILE, layout.id())
+}
+fn get_saved_sensor_mode() -> (SensorMode, bool) {
+    let file =
+        fs::read_to_string(profile::path("sensor_mode")).unwrap_or_else(|_| String::from("0;0"));
+    let vec: Vec<&str> = file.trim().split(';').collect::<Vec<&str>>();
+
+    let mode = vec[0].parse::<u8>().unwrap_or(0);
+    let ambient_compensation = vec.get(1).and_then(|a| a.parse::<u8>().ok()).unwrap_or(0);
+
+    (SensorMode::from_id(mode), ambient_compensation != 0)
+}
+fn save_sensor_mode(mode: SensorMode, ambient_compensation: bool) {
+    let file = format!("{};{}", mode.id(), ambient_compensation as u8);
+    fs::write(profile::path("sensor_mode"), file).unwrap();
+}
+
+fn get_saved_filters() -> Vec<FilterKind> {
+    fs::read_to_string(FILTERS_FILE)
+        .unwrap_or_else(|_| String::new())
+        .trim()
+        .split(';')
+        .filter_map(|stage| {
+            let vec: Vec<&str> = stage.split(':').collect::<Vec<&str>>();
+            let id = vec[0].parse::<u8>().ok()?;
+            let parameter = vec.get(1)?.parse::<f32>().ok()?;
+            FilterKind::from_id(id, parameter)
+        })
+        .collect()
+}
+fn save_filters(filters: &[FilterKind]) {
+    let file = filters
+        .iter()
+        .map(|filter| {
+            let (id, parameter) = filter.id();
+            format!("{}:{}", id, parameter)
+        })
+        .collect::<Vec<String>>()
+        .join(";");
+    fs::write(FILTERS_FILE, file).unwrap();
+}
+
+/// Open the line sensors with the saved layout, mode and filters.
+fn open_sensors() -> Ev3Result<LineSensors> {
+    let (mode, ambient_compensation) = get_saved_sensor_mode();
+    LineSensors::open(
+        get_saved_sensor_layout(),
+        mode,
+        ambient_compensation,
+        &get_saved_filters(),
+    )
+}
 
-    (r, g, b)
+/// Open the line sensors with changed settings. Returns `None` and keeps the saved settings if
+/// a sensor is missing, because the saved settings are opened again after every pid error.
+fn try_open_sensors(
+    layout: SensorLayout,
+    (mode, ambient_compensation): (SensorMode, bool),
+    filters: &[FilterKind],
+) -> Option<LineSensors> {
+    match LineSensors::open(layout, mode, ambient_compensation, filters) {
+        Ok(sensors) => Some(sensors),
+        Err(e) => {
+            println!("Sensors not available, keeping the settings! {:?}", e);
+            None
+        }
+    }
 }
-fn save_color(name: &str, foreground: &(i32, i32, i32)) {
-    let mut color = String::new();
-    color.push_str(foreground.0.to_string().as_ref());
-    color.push(';');
-    color.push_str(foreground.1.to_string().as_ref());
-    color.push(';');
-    color.push_str(foreground.2.to_string().as_ref());
 
-    fs::write(name, color).unwrap();
+fn get_saved_gains() -> Gains {
+    let file = fs::read_to_string(profile::path("gains")).unwrap_or_else(|_| String::new());
+    let vec: Vec<f32> = file
+        .trim()
+        .split(';')
+        .filter_map(|gain| gain.parse::<f32>().ok())
+        .collect();
+
+    if vec.len() == 3 {
+        Gains::new(vec[0], vec[1], vec[2])
+    } else {
+        Gains::new(CONST_PROPORTIONAL, CONST_INTEGRAL, CONST_DERIVATIVE)
+    }
 }
+fn save_gains(gains: &Gains) {
+    let file = format!(
+        "{};{};{}",
+        gains.proportional, gains.integral, gains.derivative
+    );
+    fs::write(profile::path("gains"), file).unwrap();
+}
+
+fn get_saved_gain_schedule() -> GainSchedule {
+    let points = fs::read_to_string(profile::path("gain_schedule"))
+        .unwrap_or_else(|_| String::new())
+        .trim()
+        .split(';')
+        .filter_map(|point| {
+            let vec: Vec<f32> = point
+                .split(':')
+                .filter_map(|value| value.parse::<f32>().ok())
+                .collect();
+            if vec.len() == 4 {
+                Some((vec[0], Gains::new(vec[1], vec[2], vec[3])))
+            } else {
+                None
+            }
+        })
+        .collect();
 
-fn get_saved_foreground() -> (i32, i32, i32) {
-    get_saved_color("foreground", 20)
+    GainSchedule::new(points)
 }
-fn save_foreground(color: &(i32, i32, i32)) {
-    save_color("foreground", color)
+fn save_gain_schedule(schedule: &GainSchedule) {
+    let file = schedule
+        .points()
+        .iter()
+        .map(|&(speed, gains)| {
+            format!(
+                "{}:{}:{}:{}",
+                speed, gains.proportional, gains.integral, gains.derivative
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(";");
+    fs::write(profile::path("gain_schedule"), file).unwrap();
 }
-fn get_saved_background() -> (i32, i32, i32) {
-    get_saved_color("background", 200)
+
+fn get_saved_rate() -> u32 {
+    fs::read_to_string(RATE_FILE)
+        .unwrap_or_else(|_| String::new())
+        .trim()
+        .parse::<u32>()
+        .unwrap_or(DEFAULT_RATE)
+        .max(MINIMUM_RATE)
 }
-fn save_background(color: &(i32, i32, i32)) {
-    save_color("background", color)
+fn save_rate(rate: u32) {
+    fs::write(RATE_FILE, rate.to_string()).unwrap();
 }
 
 pub enum PidCommand {
@@ -283,4 +1178,26 @@ pub enum PidCommand {
     Stop,
     SetForeground,
     SetBackground,
+    SetForegroundColor(Rgb),
+    SetBackgroundColor(Rgb),
+    GetCalibration,
+    SetLineDetection(LineDetection),
+    SetRecovery(RecoveryStrategy),
+    SetRate(u32),
+    SetGains(Gains),
+    SetGainSchedule(Vec<(f32, Gains)>),
+    AutoTune(TuningRule),
+    SetSensorLayout(SensorLayout),
+    SetSensorMode(SensorMode, bool),
+    SetFilters(Vec<FilterKind>),
+    SetMarker(Marker),
+    SetRoute(Vec<Branch>),
+    SetEdge(Edge),
+    ResetLaps,
+    CreateProfile(String),
+    SelectProfile(String),
+    DeleteProfile(String),
+    ListProfiles,
+    /// Run the self-test of the color sensors, the checks are sent back.
+    Diagnose(Sender<Vec<Check>>),
 }
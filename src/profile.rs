@@ -0,0 +1,119 @@
+use marker::Marker;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory with a sub directory per calibration profile.
+const PROFILE_DIRECTORY: &str = "profiles";
+/// File holding the name of the active profile.
+const ACTIVE_PROFILE: &str = "profile";
+/// Settings stored per profile in addition to the marker colors. All other settings are shared.
+const PROFILE_FILES: [&str; 5] = [
+    "foreground",
+    "background",
+    "sensor_mode",
+    "gains",
+    "gain_schedule",
+];
+
+/// Settings files stored per profile.
+pub fn profile_files() -> Vec<&'static str> {
+    let mut files = PROFILE_FILES.to_vec();
+    files.extend(Marker::all().into_iter().map(|marker| marker.file_name()));
+    files
+}
+
+/// Directory of the profile with the given name. Names must not leave the profile directory.
+fn directory(name: &str) -> io::Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') || name.contains('\\') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid profile name",
+        ));
+    }
+
+    Ok(Path::new(PROFILE_DIRECTORY).join(name))
+}
+
+/// Name of the active profile, `None` if the shared settings are used.
+pub fn active() -> Option<String> {
+    let name = fs::read_to_string(ACTIVE_PROFILE).ok()?.trim().to_string();
+
+    if directory(&name).ok()?.is_dir() {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// Path of a settings file in the active profile.
+pub fn path(file: &str) -> PathBuf {
+    match active().and_then(|name| directory(&name).ok()) {
+        Some(directory) => directory.join(file),
+        None => PathBuf::from(file),
+    }
+}
+
+/// Names of all profiles in alphabetical order.
+pub fn list() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(PROFILE_DIRECTORY)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_else(|_| Vec::new());
+
+    names.sort();
+    names
+}
+
+/// Create a profile from the current settings and activate it.
+pub fn create(name: &str) -> io::Result<()> {
+    let directory = directory(name)?;
+    fs::create_dir_all(&directory)?;
+
+    for file in profile_files() {
+        let current = path(file);
+        if current.exists() {
+            fs::copy(current, directory.join(file))?;
+        }
+    }
+
+    fs::write(ACTIVE_PROFILE, name)
+}
+
+/// Activate the profile with the given name. An empty name activates the shared settings.
+pub fn select(name: &str) -> io::Result<()> {
+    if name.is_empty() {
+        return remove_active();
+    }
+
+    if !directory(name)?.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "unknown profile"));
+    }
+
+    fs::write(ACTIVE_PROFILE, name)
+}
+
+/// Delete the profile with the given name. Deleting the active profile activates the shared
+/// settings.
+pub fn delete(name: &str) -> io::Result<()> {
+    fs::remove_dir_all(directory(name)?)?;
+
+    if active().is_none() {
+        remove_active()?;
+    }
+
+    Ok(())
+}
+
+fn remove_active() -> io::Result<()> {
+    if Path::new(ACTIVE_PROFILE).exists() {
+        fs::remove_file(ACTIVE_PROFILE)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::time::{Duration, Instant};
+
+/// Time after which the search is given up.
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time in seconds to reverse before searching again.
+const BACKUP_TIME: f32 = 0.4;
+/// Time in seconds to spin after backing up before backing up again.
+const BACKUP_SPIN_TIME: f32 = 1.2;
+/// Time in seconds of the first sweep, every further sweep gets longer by this amount.
+const SWEEP_TIME: f32 = 0.3;
+
+/// Strategy to find the line again after it was lost.
+#[derive(Clone, Copy)]
+pub enum RecoveryStrategy {
+    /// Spin in place toward the side the line was last seen.
+    Spin,
+    /// Reverse for a moment, then spin toward the line, and repeat.
+    Backup,
+    /// Turn left and right with growing amplitude.
+    Sweep,
+}
+
+impl RecoveryStrategy {
+    pub fn from_id(id: u8) -> RecoveryStrategy {
+        match id {
+            1 => RecoveryStrategy::Backup,
+            2 => RecoveryStrategy::Sweep,
+            _ => RecoveryStrategy::Spin,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            RecoveryStrategy::Spin => 0,
+            RecoveryStrategy::Backup => 1,
+            RecoveryStrategy::Sweep => 2,
+        }
+    }
+}
+
+/// Non blocking line search, driven by the control loop.
+pub struct LineSearch {
+    strategy: RecoveryStrategy,
+    direction: f32,
+    started: Instant,
+    time: f32,
+    width: f32,
+}
+
+impl LineSearch {
+    /// Start a new search. The sign of `direction` gives the side the line is expected on.
+    pub fn new(strategy: RecoveryStrategy, direction: f32) -> LineSearch {
+        LineSearch {
+            strategy,
+            direction: if direction < 0.0 { -1.0 } else { 1.0 },
+            started: Instant::now(),
+            time: 0.0,
+            width: SWEEP_TIME,
+        }
+    }
+
+    /// Calculate the next wheel speeds after the time step in seconds. Returns `None` if the
+    /// search timed out.
+    pub fn update(&mut self, speed: f32, dt: f32) -> Option<(f32, f32)> {
+        if self.started.elapsed() > SEARCH_TIMEOUT {
+            return None;
+        }
+
+        self.time += dt;
+
+        let turn = speed * self.direction;
+        match self.strategy {
+            RecoveryStrategy::Spin => Some((turn, -turn)),
+            RecoveryStrategy::Backup => {
+                if self.time > BACKUP_TIME + BACKUP_SPIN_TIME {
+                    self.time = 0.0;
+                }
+
+                if self.time <= BACKUP_TIME {
+                    Some((-speed, -speed))
+                } else {
+                    Some((turn, -turn))
+                }
+            }
+            RecoveryStrategy::Sweep => {
+                if self.time > self.width {
+                    self.time = 0.0;
+                    self.width += SWEEP_TIME;
+                    self.direction = -self.direction;
+                }
+
+                Some((speed * self.direction, -speed * self.direction))
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::{Duration, Instant};
+
+/// Error below which the sensor is considered to be fully on the line.
+const JUNCTION_THRESHOLD: f32 = -0.9;
+/// Time in seconds fully on the line that signals a crossing line.
+const JUNCTION_TIME: f32 = 0.16;
+/// Time in seconds to drive straight to reach the center of the junction.
+const BRANCH_STRAIGHT_TIME: f32 = 0.3;
+/// Minimal time in seconds to turn before a line is accepted as the new branch.
+const BRANCH_TURN_TIME: f32 = 0.2;
+/// Time after which a turn that did not find the branch is given up.
+const BRANCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Branch to take at a junction.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Branch {
+    Left,
+    Straight,
+    Right,
+}
+
+impl Branch {
+    pub fn from_id(id: u8) -> Option<Branch> {
+        match id {
+            0 => Some(Branch::Left),
+            1 => Some(Branch::Straight),
+            2 => Some(Branch::Right),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            Branch::Left => 0,
+            Branch::Straight => 1,
+            Branch::Right => 2,
+        }
+    }
+}
+
+/// Sequence of branches to take at the upcoming junctions.
+pub struct Route {
+    branches: Vec<Branch>,
+    position: usize,
+}
+
+impl Route {
+    pub fn new() -> Route {
+        Route {
+            branches: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn set(&mut self, branches: Vec<Branch>) {
+        self.branches = branches;
+        self.position = 0;
+    }
+
+    pub fn reset(&mut self) {
+        self.position = 0;
+    }
+
+    /// Whether no branches are set, so the line is simply followed across junctions.
+    pub fn is_empty(&self) -> bool {
+        self.branches.is_empty()
+    }
+
+    /// Advance to the next junction. Returns the index of the junction and the branch to take.
+    /// Junctions beyond the end of the route are passed straight.
+    pub fn next(&mut self) -> (usize, Branch) {
+        let index = self.position;
+        self.position += 1;
+
+        (
+            index,
+            self.branches
+                .get(index)
+                .cloned()
+                .unwrap_or(Branch::Straight),
+        )
+    }
+}
+
+/// Detects junctions by the sensor signal: a crossing line keeps the sensor fully on the line
+/// for longer than the edge of a single line would.
+pub struct JunctionDetector {
+    time: f32,
+}
+
+impl JunctionDetector {
+    pub fn new() -> JunctionDetector {
+        JunctionDetector { time: 0.0 }
+    }
+
+    pub fn reset(&mut self) {
+        self.time = 0.0;
+    }
+
+    /// Feed the next line error, normalized so that negative values lie on the line, and the
+    /// time step in seconds. Returns true once when a junction is reached.
+    pub fn update(&mut self, error: f32, dt: f32) -> bool {
+        let last = self.time;
+        if error < JUNCTION_THRESHOLD {
+            self.time += dt;
+        } else {
+            self.time = 0.0;
+        }
+
+        last <= JUNCTION_TIME && self.time > JUNCTION_TIME
+    }
+}
+
+/// Open loop manoeuvre to leave a junction on the requested branch.
+pub struct BranchManoeuvre {
+    branch: Branch,
+    started: Instant,
+    time: f32,
+}
+
+impl BranchManoeuvre {
+    pub fn new(branch: Branch) -> BranchManoeuvre {
+        BranchManoeuvre {
+            branch,
+            started: Instant::now(),
+            time: 0.0,
+        }
+    }
+
+    /// Whether the turn did not find the branch in time, for example after a false junction.
+    pub fn timed_out(&self) -> bool {
+        self.started.elapsed() > BRANCH_TIMEOUT
+    }
+
+    /// Calculate the next wheel speeds after the time step in seconds. Returns `None` when the
+    /// new branch is reached or the manoeuvre timed out.
+    pub fn update(&mut self, on_line: bool, speed: f32, dt: f32) -> Option<(f32, f32)> {
+        if self.timed_out() {
+            return None;
+        }
+
+        self.time += dt;
+
+        if self.time <= BRANCH_STRAIGHT_TIME {
+            return Some((speed, speed));
+        }
+
+        if on_line && self.time > BRANCH_STRAIGHT_TIME + BRANCH_TURN_TIME {
+            return None;
+        }
+
+        match self.branch {
+            Branch::Left => Some((-speed, speed)),
+            Branch::Straight => None,
+            Branch::Right => Some((speed, -speed)),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Runs a control loop at a fixed rate and measures the real time step.
+pub struct Scheduler {
+    period: Duration,
+    next: Instant,
+    last: Instant,
+    last_report: Instant,
+    overruns: u32,
+}
+
+impl Scheduler {
+    /// Create a scheduler for the given rate in Hz.
+    pub fn new(rate: u32) -> Scheduler {
+        let now = Instant::now();
+        let period = period(rate);
+
+        Scheduler {
+            period,
+            next: now + period,
+            last: now,
+            last_report: now,
+            overruns: 0,
+        }
+    }
+
+    pub fn set_rate(&mut self, rate: u32) {
+        self.period = period(rate);
+    }
+
+    /// Sleep until the next cycle is due. Returns the elapsed time since the last cycle in seconds.
+    ///
+    /// If the last cycle took longer than the period, the overrun is counted and the schedule
+    /// restarts from now instead of trying to catch up.
+    pub fn wait(&mut self) -> f32 {
+        let now = Instant::now();
+        if now < self.next {
+            thread::sleep(self.next - now);
+            self.next += self.period;
+        } else {
+            self.overruns += 1;
+            self.next = now + self.period;
+        }
+
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+
+        elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1_000_000_000.0
+    }
+
+    /// Returns the number of overruns once per report interval if any occurred.
+    pub fn report(&mut self) -> Option<u32> {
+        if self.last_report.elapsed() < REPORT_INTERVAL {
+            return None;
+        }
+
+        let overruns = self.overruns;
+        self.overruns = 0;
+        self.last_report = Instant::now();
+
+        if overruns > 0 {
+            Some(overruns)
+        } else {
+            None
+        }
+    }
+}
+
+fn period(rate: u32) -> Duration {
+    Duration::from_micros(1_000_000 / u64::from(rate.max(1)))
+}
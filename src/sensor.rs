@@ -0,0 +1,255 @@
+use color::Rgb;
+use ev3dev_lang_rust::sensors::{ColorSensor, Sensor, SensorPort};
+use ev3dev_lang_rust::Ev3Result;
+use filter::{ColorFilter, FilterKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Port of the left sensor if two sensors straddle the line.
+const LEFT_SENSOR_PORT: SensorPort = SensorPort::In1;
+/// Port of the right sensor if two sensors straddle the line.
+const RIGHT_SENSOR_PORT: SensorPort = SensorPort::In2;
+/// Interval between two ambient light samples.
+const AMBIENT_INTERVAL: Duration = Duration::from_secs(2);
+/// Time after a mode switch until the sensor returns readings of the new mode.
+const MODE_SETTLE: Duration = Duration::from_millis(50);
+/// Scale of percentage readings (reflected and ambient light) to the range of raw rgb readings.
+const PERCENT_SCALE: i32 = 10;
+
+/// Arrangement of the color sensors used for line following.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SensorLayout {
+    /// A single sensor following one edge of the line.
+    Single,
+    /// Two sensors straddling the line, left and right of it.
+    Dual,
+}
+
+impl SensorLayout {
+    pub fn from_id(id: u8) -> SensorLayout {
+        match id {
+            1 => SensorLayout::Dual,
+            _ => SensorLayout::Single,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            SensorLayout::Single => 0,
+            SensorLayout::Dual => 1,
+        }
+    }
+}
+
+/// Measurement mode of the color sensors.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SensorMode {
+    /// Raw red, green and blue channels.
+    RgbRaw,
+    /// Reflected light intensity. Cheaper to read, but without any color information.
+    Reflect,
+}
+
+impl SensorMode {
+    pub fn from_id(id: u8) -> SensorMode {
+        match id {
+            1 => SensorMode::Reflect,
+            _ => SensorMode::RgbRaw,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            SensorMode::RgbRaw => 0,
+            SensorMode::Reflect => 1,
+        }
+    }
+}
+
+/// Progress of a periodic ambient light sample. The sensors need time to settle after every
+/// mode switch, the control loop keeps running with the last readings meanwhile.
+#[derive(Clone, Copy)]
+enum AmbientPhase {
+    Idle,
+    /// Switched to ambient light at the given time.
+    Ambient(Instant),
+    /// Switched back to the measurement mode at the given time.
+    Restore(Instant),
+}
+
+/// The color sensors of the line follower.
+pub struct LineSensors {
+    primary: ColorSensor,
+    secondary: Option<ColorSensor>,
+    mode: SensorMode,
+    ambient_compensation: bool,
+    ambient: (i32, Option<i32>),
+    last_ambient: Option<Instant>,
+    ambient_phase: AmbientPhase,
+    filters: (ColorFilter, ColorFilter),
+    raw: Rgb,
+    last: (Rgb, Option<Rgb>),
+}
+
+impl LineSensors {
+    /// Open the sensors of the given layout. With ambient compensation the ambient light is
+    /// sampled periodically and subtracted from all readings. The filters are applied to the
+    /// readings of each sensor.
+    pub fn open(
+        layout: SensorLayout,
+        mode: SensorMode,
+        ambient_compensation: bool,
+        filters: &[FilterKind],
+    ) -> Ev3Result<LineSensors> {
+        let (primary, secondary) = match layout {
+            SensorLayout::Single => (ColorSensor::find()?, None),
+            SensorLayout::Dual => (
+                ColorSensor::get(LEFT_SENSOR_PORT)?,
+                Some(ColorSensor::get(RIGHT_SENSOR_PORT)?),
+            ),
+        };
+
+        let mut sensors = LineSensors {
+            primary,
+            secondary,
+            mode,
+            ambient_compensation,
+            ambient: (0, None),
+            last_ambient: None,
+            ambient_phase: AmbientPhase::Idle,
+            filters: (ColorFilter::new(filters), ColorFilter::new(filters)),
+            raw: (0, 0, 0),
+            last: ((0, 0, 0), None),
+        };
+
+        // The first sample may block, the control loop is not running yet
+        if ambient_compensation {
+            sensors.set_ambient_mode()?;
+            thread::sleep(MODE_SETTLE);
+            sensors.sample_ambient()?;
+        }
+        sensors.set_mode()?;
+        thread::sleep(MODE_SETTLE);
+
+        Ok(sensors)
+    }
+
+    fn set_mode(&self) -> Ev3Result<()> {
+        set_mode(&self.primary, self.mode)?;
+        if let Some(ref secondary) = self.secondary {
+            set_mode(secondary, self.mode)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_ambient_mode(&self) -> Ev3Result<()> {
+        self.primary.set_mode_col_ambient()?;
+        if let Some(ref secondary) = self.secondary {
+            secondary.set_mode_col_ambient()?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the ambient light, the sensors must have settled in the ambient mode.
+    fn sample_ambient(&mut self) -> Ev3Result<()> {
+        let primary = self.primary.get_value0()? * PERCENT_SCALE;
+        let secondary = match self.secondary {
+            Some(ref secondary) => Some(secondary.get_value0()? * PERCENT_SCALE),
+            None => None,
+        };
+
+        self.ambient = (primary, secondary);
+        self.last_ambient = Some(Instant::now());
+
+        Ok(())
+    }
+
+    /// Advance the periodic ambient light sample. Returns true while the sensors do not return
+    /// readings of the measurement mode.
+    fn update_ambient(&mut self) -> Ev3Result<bool> {
+        match self.ambient_phase {
+            AmbientPhase::Idle => {
+                let due = match self.last_ambient {
+                    Some(time) => time.elapsed() > AMBIENT_INTERVAL,
+                    None => true,
+                };
+                if !due {
+                    return Ok(false);
+                }
+
+                self.set_ambient_mode()?;
+                self.ambient_phase = AmbientPhase::Ambient(Instant::now());
+            }
+            AmbientPhase::Ambient(time) => {
+                if time.elapsed() >= MODE_SETTLE {
+                    self.sample_ambient()?;
+                    self.set_mode()?;
+                    self.ambient_phase = AmbientPhase::Restore(Instant::now());
+                }
+            }
+            AmbientPhase::Restore(time) => {
+                if time.elapsed() >= MODE_SETTLE {
+                    self.ambient_phase = AmbientPhase::Idle;
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Last unfiltered reading of the primary sensor.
+    pub fn raw(&self) -> Rgb {
+        self.raw
+    }
+
+    /// Read and filter the primary (the single or the left) and, if present, the secondary
+    /// sensor. While the ambient light is sampled the last readings are returned.
+    pub fn read(&mut self) -> Ev3Result<(Rgb, Option<Rgb>)> {
+        if self.ambient_compensation && self.update_ambient()? {
+            return Ok(self.last);
+        }
+
+        self.raw = compensate(read(&self.primary, self.mode)?, self.ambient.0);
+        let primary = self.filters.0.update(&self.raw);
+
+        let secondary = match self.secondary {
+            Some(ref secondary) => {
+                let raw = compensate(read(secondary, self.mode)?, self.ambient.1.unwrap_or(0));
+                Some(self.filters.1.update(&raw))
+            }
+            None => None,
+        };
+
+        self.last = (primary, secondary);
+        Ok(self.last)
+    }
+}
+
+fn set_mode(sensor: &ColorSensor, mode: SensorMode) -> Ev3Result<()> {
+    match mode {
+        SensorMode::RgbRaw => sensor.set_mode_rgb_raw(),
+        SensorMode::Reflect => sensor.set_mode_col_reflect(),
+    }
+}
+
+/// Read a sensor in the given mode. Reflected light is scaled to the range of raw readings.
+fn read(sensor: &ColorSensor, mode: SensorMode) -> Ev3Result<Rgb> {
+    match mode {
+        SensorMode::RgbRaw => sensor.get_rgb(),
+        SensorMode::Reflect => {
+            let intensity = sensor.get_value0()? * PERCENT_SCALE;
+            Ok((intensity, intensity, intensity))
+        }
+    }
+}
+
+fn compensate(sensor: Rgb, ambient: i32) -> Rgb {
+    (
+        (sensor.0 - ambient).max(0),
+        (sensor.1 - ambient).max(0),
+        (sensor.2 - ambient).max(0),
+    )
+}
@@ -0,0 +1,27 @@
+/// Shaping of a manual input between -1 and 1.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InputShaping {
+    /// Inputs below this magnitude are ignored, the remaining range is stretched to full scale.
+    pub deadband: f32,
+    /// Blend between a linear (0) and a cubic (1) response for finer control around zero.
+    pub expo: f32,
+}
+
+impl InputShaping {
+    pub fn new(deadband: f32, expo: f32) -> InputShaping {
+        InputShaping {
+            deadband: deadband.max(0.0).min(0.99),
+            expo: expo.max(0.0).min(1.0),
+        }
+    }
+
+    pub fn apply(&self, value: f32) -> f32 {
+        let value = value.max(-1.0).min(1.0);
+        if value.abs() <= self.deadband {
+            return 0.0;
+        }
+
+        let scaled = (value.abs() - self.deadband) / (1.0 - self.deadband) * value.signum();
+        (1.0 - self.expo) * scaled + self.expo * scaled.powi(3)
+    }
+}
@@ -0,0 +1,81 @@
+/// Limits of the speed changes of a wheel. Zero disables a limit.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SlewLimits {
+    /// Maximal change of the speed per second.
+    pub acceleration: f32,
+    /// Maximal change of the acceleration per second.
+    pub jerk: f32,
+}
+
+impl SlewLimits {
+    pub fn new(acceleration: f32, jerk: f32) -> SlewLimits {
+        SlewLimits {
+            acceleration: acceleration.max(0.0),
+            jerk: jerk.max(0.0),
+        }
+    }
+}
+
+/// Moves the speed of a wheel towards its target within the acceleration and jerk limits.
+pub struct SlewLimiter {
+    limits: SlewLimits,
+    speed: f32,
+    acceleration: f32,
+}
+
+impl SlewLimiter {
+    pub fn new(limits: SlewLimits) -> SlewLimiter {
+        SlewLimiter {
+            limits,
+            speed: 0.0,
+            acceleration: 0.0,
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: SlewLimits) {
+        self.limits = limits;
+    }
+
+    /// Jump to the given speed without any limits, for example to stop at once.
+    pub fn reset(&mut self, speed: f32) {
+        self.speed = speed;
+        self.acceleration = 0.0;
+    }
+
+    /// Calculate the next speed towards the target after `dt` seconds.
+    pub fn update(&mut self, target: f32, dt: f32) -> f32 {
+        if dt <= 0.0 {
+            return self.speed;
+        }
+
+        let required = (target - self.speed) / dt;
+        let mut acceleration = required;
+
+        if self.limits.acceleration > 0.0 {
+            acceleration = acceleration
+                .max(-self.limits.acceleration)
+                .min(self.limits.acceleration);
+        }
+
+        if self.limits.jerk > 0.0 {
+            // Reduce the acceleration early enough to reach the target without overshooting it
+            let braking = (2.0 * self.limits.jerk * (target - self.speed).abs()).sqrt();
+            acceleration = acceleration
+                .max(self.acceleration - self.limits.jerk * dt)
+                .min(self.acceleration + self.limits.jerk * dt)
+                .max(-braking)
+                .min(braking);
+        }
+
+        // Never step past the target
+        acceleration = if required >= 0.0 {
+            acceleration.min(required)
+        } else {
+            acceleration.max(required)
+        };
+
+        self.acceleration = acceleration;
+        self.speed += acceleration * dt;
+        self.speed
+    }
+}
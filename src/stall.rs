@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+/// Duty cycle above which a motor is expected to turn.
+const STALL_DUTY: i32 = 30;
+/// Encoder speed in degrees per second below which a driven motor counts as blocked.
+const STALL_SPEED: i32 = 50;
+/// Time a motor has to be blocked before it counts as stalled.
+const STALL_TIME: Duration = Duration::from_millis(500);
+/// Duty cycle limit of a stalled motor.
+const STALL_POWER: i32 = 20;
+
+/// Detects a drive motor that is blocked while it is driven, for example against a wall.
+pub struct StallDetector {
+    blocked: Option<Instant>,
+    stalled: Option<i32>,
+}
+
+impl StallDetector {
+    pub fn new() -> StallDetector {
+        StallDetector {
+            blocked: None,
+            stalled: None,
+        }
+    }
+
+    /// Feed the commanded duty cycle, the encoder speed and whether the motor reports a stall or
+    /// overload. Returns true once when the motor stalls.
+    pub fn update(&mut self, duty_cycle: i32, speed: i32, flagged: bool) -> bool {
+        if let Some(stalled_duty_cycle) = self.stalled {
+            // Released when the motor moves again or is commanded to stop or reverse
+            let released = duty_cycle.abs() < STALL_DUTY
+                || duty_cycle.signum() != stalled_duty_cycle.signum()
+                || speed.abs() >= STALL_SPEED;
+            if released {
+                self.stalled = None;
+                self.blocked = None;
+            }
+            return false;
+        }
+
+        let blocked = duty_cycle.abs() >= STALL_DUTY && (speed.abs() < STALL_SPEED || flagged);
+        if !blocked {
+            self.blocked = None;
+            return false;
+        }
+
+        match self.blocked {
+            Some(time) if time.elapsed() > STALL_TIME => {
+                self.stalled = Some(duty_cycle);
+                true
+            }
+            Some(_) => false,
+            None => {
+                self.blocked = Some(Instant::now());
+                false
+            }
+        }
+    }
+
+    /// Limit the duty cycle while the motor is stalled.
+    pub fn limit(&self, duty_cycle: i32) -> i32 {
+        match self.stalled {
+            Some(_) => duty_cycle.max(-STALL_POWER).min(STALL_POWER),
+            None => duty_cycle,
+        }
+    }
+}
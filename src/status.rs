@@ -1,6 +1,7 @@
 use std::fs;
 use std::path::Path;
 
+use battery::BatteryState;
 use ev3dev_lang_rust::{Ev3Result, Led, PowerSupply};
 
 const COLOR_LIME: &str = "lime";
@@ -14,6 +15,7 @@ pub struct Status {
     led: Led,
     power: PowerSupply,
     connection: ConnectionState,
+    battery: BatteryState,
 }
 
 impl Status {
@@ -22,6 +24,7 @@ impl Status {
             led: Led::new().unwrap(),
             power: PowerSupply::new().unwrap(),
             connection: ConnectionState::Disconnected,
+            battery: BatteryState::Ok,
         };
 
         if !Path::new("name").exists() {
@@ -65,7 +68,22 @@ impl Status {
         self.load_color()
     }
 
+    /// Update the battery state. The leds are only rewritten when the state changes.
+    pub fn set_battery_state(&mut self, battery: BatteryState) {
+        if self.battery == battery {
+            return;
+        }
+
+        self.battery = battery;
+        self.load_color()
+    }
+
     fn load_color(&mut self) {
+        // The monitor blinks the leds while the battery is low
+        if self.battery != BatteryState::Ok {
+            return;
+        }
+
         let main_color = match self.get_color().as_ref() {
             COLOR_LIME => Led::COLOR_GREEN,
             COLOR_YELLOW => Led::COLOR_YELLOW,
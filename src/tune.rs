@@ -0,0 +1,151 @@
+use controller::Gains;
+use std::f32;
+
+/// Controller output of the relay.
+const RELAY_AMPLITUDE: f32 = 0.6;
+/// Error band around zero in which the relay keeps its last output.
+const RELAY_HYSTERESIS: f32 = 0.05;
+/// Number of oscillation periods ignored until the oscillation has settled.
+const SETTLE_PERIODS: usize = 2;
+/// Number of oscillation periods averaged for the estimate.
+const MEASURE_PERIODS: usize = 4;
+/// Maximal duration of the experiment in seconds.
+const TUNE_TIMEOUT: f32 = 20.0;
+
+/// Rule to derive the controller gains from the ultimate gain and period.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TuningRule {
+    /// Fast response with little damping.
+    ZieglerNichols,
+    /// More conservative gains with less overshoot.
+    TyreusLuyben,
+}
+
+impl TuningRule {
+    pub fn from_id(id: u8) -> TuningRule {
+        match id {
+            1 => TuningRule::TyreusLuyben,
+            _ => TuningRule::ZieglerNichols,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            TuningRule::ZieglerNichols => 0,
+            TuningRule::TyreusLuyben => 1,
+        }
+    }
+
+    /// Gains for the given ultimate gain and ultimate period in seconds.
+    pub fn gains(self, ultimate_gain: f32, ultimate_period: f32) -> Gains {
+        let (proportional, integral_time, derivative_time) = match self {
+            TuningRule::ZieglerNichols => (
+                0.6 * ultimate_gain,
+                ultimate_period / 2.0,
+                ultimate_period / 8.0,
+            ),
+            TuningRule::TyreusLuyben => (
+                ultimate_gain / 2.2,
+                2.2 * ultimate_period,
+                ultimate_period / 6.3,
+            ),
+        };
+
+        Gains::new(
+            proportional,
+            proportional / integral_time,
+            proportional * derivative_time,
+        )
+    }
+}
+
+/// Result of a relay feedback experiment.
+#[derive(Clone, Copy, Debug)]
+pub struct Tuning {
+    pub rule: TuningRule,
+    pub ultimate_gain: f32,
+    pub ultimate_period: f32,
+    pub gains: Gains,
+}
+
+/// Relay feedback experiment. The relay lets the robot oscillate around the line, the gain and
+/// period at the stability limit are estimated from the amplitude and period of the oscillation.
+pub struct AutoTune {
+    rule: TuningRule,
+    output: f32,
+    time: f32,
+    last_switch: Option<f32>,
+    periods: Vec<f32>,
+    amplitudes: Vec<f32>,
+    minimum: f32,
+    maximum: f32,
+}
+
+impl AutoTune {
+    pub fn new(rule: TuningRule) -> AutoTune {
+        AutoTune {
+            rule,
+            output: RELAY_AMPLITUDE,
+            time: 0.0,
+            last_switch: None,
+            periods: Vec::new(),
+            amplitudes: Vec::new(),
+            minimum: f32::INFINITY,
+            maximum: f32::NEG_INFINITY,
+        }
+    }
+
+    /// Calculate the relay output for the given error and time step in seconds. Returns `None`
+    /// when the experiment is finished.
+    pub fn update(&mut self, error: f32, dt: f32) -> Option<f32> {
+        self.time += dt;
+        if self.time > TUNE_TIMEOUT || self.periods.len() >= SETTLE_PERIODS + MEASURE_PERIODS {
+            return None;
+        }
+
+        self.minimum = self.minimum.min(error);
+        self.maximum = self.maximum.max(error);
+
+        if error > RELAY_HYSTERESIS && self.output < 0.0 {
+            // A full period ends with every switch to the positive output
+            if let Some(last_switch) = self.last_switch {
+                self.periods.push(self.time - last_switch);
+                self.amplitudes.push((self.maximum - self.minimum) / 2.0);
+            }
+            self.last_switch = Some(self.time);
+            self.minimum = error;
+            self.maximum = error;
+            self.output = RELAY_AMPLITUDE;
+        } else if error < -RELAY_HYSTERESIS && self.output > 0.0 {
+            self.output = -RELAY_AMPLITUDE;
+        }
+
+        Some(self.output)
+    }
+
+    /// Estimate the gains from the measured oscillation. Returns `None` if the experiment timed
+    /// out before enough periods were measured.
+    pub fn result(&self) -> Option<Tuning> {
+        if self.periods.len() < SETTLE_PERIODS + MEASURE_PERIODS {
+            return None;
+        }
+
+        let count = MEASURE_PERIODS as f32;
+        let period = self.periods[SETTLE_PERIODS..].iter().sum::<f32>() / count;
+        let amplitude = self.amplitudes[SETTLE_PERIODS..].iter().sum::<f32>() / count;
+
+        // Describing function of a relay with hysteresis
+        let amplitude = (amplitude * amplitude - RELAY_HYSTERESIS * RELAY_HYSTERESIS).sqrt();
+        if amplitude.is_nan() || amplitude <= 0.0 || period <= 0.0 {
+            return None;
+        }
+        let ultimate_gain = 4.0 * RELAY_AMPLITUDE / (f32::consts::PI * amplitude);
+
+        Some(Tuning {
+            rule: self.rule,
+            ultimate_gain,
+            ultimate_period: period,
+            gains: self.rule.gains(ultimate_gain, period),
+        })
+    }
+}